@@ -0,0 +1,424 @@
+use std::ops::Range;
+
+use crate::{
+    instruction::Instruction::{self, *},
+    translate::{find_begin_loop, find_end_loop, PADDING},
+};
+
+/// A sequence of nodes.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Block(pub Vec<Node>);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    Op(Instruction),
+    /// `MOO body moo`, which runs `body` while the current memory block isn't 0.
+    Loop {
+        body: Block,
+    },
+}
+
+/// Parses a program into a [`Block`].
+///
+/// `MOO` skips the command right after it when searching for its `moo`, and `moo` skips the
+/// command right before it when searching for its `MOO`, so the two don't always agree. A
+/// [`Node::Loop`] is only made when they do, which makes it behave exactly like a `while` loop.
+/// Other `MOO`s and `moo`s are kept as [`Node::Op`]s: they jump to wherever the interpreter
+/// would, or fail at run time. `mOO` may run a loop command too.
+pub fn parse(program: &[Instruction]) -> Block {
+    parse_range(program, 0, program.len())
+}
+
+fn parse_range(program: &[Instruction], start: usize, end: usize) -> Block {
+    let mut nodes = vec![];
+    let mut index = start;
+    while index < end {
+        let matching = match program[index] {
+            BeginLoop => find_end_loop(program, index)
+                .filter(|&last| last < end && find_begin_loop(program, last) == Some(index)),
+            _ => None,
+        };
+        match matching {
+            Some(last) => {
+                nodes.push(Node::Loop {
+                    body: parse_range(program, index + 1, last),
+                });
+                index = last + 1;
+            }
+            None => {
+                nodes.push(Node::Op(program[index]));
+                index += 1;
+            }
+        }
+    }
+    Block(nodes)
+}
+
+impl Block {
+    /// Number of instructions in the block.
+    pub fn len(&self) -> usize {
+        self.0.iter().map(Node::size).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the instruction ranges of the loops, from `MOO` to `moo` included, in program
+    /// order so that outer loops come before the loops they contain. `index` is the position of
+    /// the block in the program.
+    pub fn loops(&self, index: usize) -> Vec<Range<usize>> {
+        let mut loops = Loops(vec![]);
+        loops.visit_block(index, self);
+        loops.0
+    }
+
+    /// Returns `false` if the block has a `MOO` or `moo` which isn't part of a [`Node::Loop`].
+    pub fn is_structured(&self) -> bool {
+        self.0.iter().all(|node| match node {
+            Node::Op(instruction) => !instruction.is_loop(),
+            Node::Loop { body } => body.is_structured(),
+        })
+    }
+
+    /// Flattens the block back into a program.
+    ///
+    /// The interpreter skips the command right next to a loop command when it searches for its
+    /// match, so a loop whose body is empty, or starts or ends with another loop, is padded with
+    /// `MoO MOo`. A `MOO` or `moo` [`Node::Op`] there isn't, as it's skipped the same way it was
+    /// in the parsed program. Blocks made by [`parse`] never need padding.
+    pub fn to_program(&self) -> Vec<Instruction> {
+        let mut program = vec![];
+        self.flatten_into(&mut program);
+        program
+    }
+
+    fn flatten_into(&self, program: &mut Vec<Instruction>) {
+        for node in &self.0 {
+            match node {
+                Node::Op(instruction) => program.push(*instruction),
+                Node::Loop { body } => {
+                    program.push(BeginLoop);
+                    if matches!(body.0.first(), None | Some(Node::Loop { .. })) {
+                        program.extend(PADDING);
+                    }
+                    body.flatten_into(program);
+                    if matches!(body.0.last(), Some(Node::Loop { .. })) {
+                        program.extend(PADDING);
+                    }
+                    program.push(EndLoop);
+                }
+            }
+        }
+    }
+}
+
+impl Node {
+    /// Number of instructions in the node, including `MOO` and `moo` of a loop.
+    pub fn size(&self) -> usize {
+        match self {
+            Self::Op(_) => 1,
+            Self::Loop { body } => body.len() + 2,
+        }
+    }
+}
+
+/// Walks a [`Block`] without changing it. `index` is the position of the first instruction of
+/// the visited item in the program it was parsed from.
+///
+/// Every method defaults to visiting the children, so an implementation only overrides what it
+/// cares about and calls the matching `walk_*` function to keep going deeper.
+pub trait Visitor {
+    fn visit_block(&mut self, index: usize, block: &Block) {
+        walk_block(self, index, block);
+    }
+
+    fn visit_op(&mut self, _index: usize, _instruction: Instruction) {}
+
+    /// `index` is the position of `MOO`.
+    fn visit_loop(&mut self, index: usize, body: &Block) {
+        walk_loop(self, index, body);
+    }
+}
+
+pub fn walk_block<V: Visitor + ?Sized>(visitor: &mut V, mut index: usize, block: &Block) {
+    for node in &block.0 {
+        match node {
+            Node::Op(instruction) => visitor.visit_op(index, *instruction),
+            Node::Loop { body } => visitor.visit_loop(index, body),
+        }
+        index += node.size();
+    }
+}
+
+pub fn walk_loop<V: Visitor + ?Sized>(visitor: &mut V, index: usize, body: &Block) {
+    visitor.visit_block(index + 1, body);
+}
+
+/// Rebuilds a [`Block`], e.g. to optimize it.
+///
+/// Every method defaults to rebuilding the node as it was, after folding its children.
+pub trait Fold {
+    fn fold_block(&mut self, block: Block) -> Block {
+        Block(
+            block
+                .0
+                .into_iter()
+                .map(|node| self.fold_node(node))
+                .collect(),
+        )
+    }
+
+    fn fold_node(&mut self, node: Node) -> Node {
+        match node {
+            Node::Op(instruction) => self.fold_op(instruction),
+            Node::Loop { body } => self.fold_loop(body),
+        }
+    }
+
+    fn fold_op(&mut self, instruction: Instruction) -> Node {
+        Node::Op(instruction)
+    }
+
+    fn fold_loop(&mut self, body: Block) -> Node {
+        Node::Loop {
+            body: self.fold_block(body),
+        }
+    }
+}
+
+/// Collects the ranges of the loops.
+struct Loops(Vec<Range<usize>>);
+
+impl Visitor for Loops {
+    fn visit_loop(&mut self, index: usize, body: &Block) {
+        self.0.push(index..index + body.len() + 2);
+        walk_loop(self, index, body);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+
+    #[test]
+    fn parse_works() {
+        // MoO MOO moO MOO MOo moo mOo moo
+        let program = [
+            IncrementByte,
+            BeginLoop,
+            IncrementPointer,
+            BeginLoop,
+            DecrementByte,
+            EndLoop,
+            DecrementPointer,
+            EndLoop,
+        ];
+        let block = parse(&program);
+
+        assert_eq!(
+            block,
+            Block(vec![
+                Node::Op(IncrementByte),
+                Node::Loop {
+                    body: Block(vec![
+                        Node::Op(IncrementPointer),
+                        Node::Loop {
+                            body: Block(vec![Node::Op(DecrementByte)]),
+                        },
+                        Node::Op(DecrementPointer),
+                    ]),
+                },
+            ])
+        );
+        assert!(block.is_structured());
+        assert_eq!(block.to_program(), program);
+    }
+
+    #[test]
+    fn parse_follows_skipping() {
+        // OOO MOO moo moo - `MOO` matches the second `moo`, and the first one has no `MOO`.
+        let program = [SetZero, BeginLoop, EndLoop, EndLoop];
+        let block = parse(&program);
+
+        assert_eq!(
+            block,
+            Block(vec![
+                Node::Op(SetZero),
+                Node::Loop {
+                    body: Block(vec![Node::Op(EndLoop)]),
+                },
+            ])
+        );
+        assert!(!block.is_structured());
+        assert_eq!(block.to_program(), program);
+
+        // MOO MOO moo moo - both `moo`s find a `MOO`, but the second one finds the inner one.
+        let program = [BeginLoop, BeginLoop, EndLoop, EndLoop];
+        assert_eq!(
+            parse(&program),
+            Block(vec![
+                Node::Loop {
+                    body: Block(vec![Node::Op(BeginLoop)]),
+                },
+                Node::Op(EndLoop),
+            ])
+        );
+    }
+
+    #[test]
+    fn visitor_works() {
+        struct Loops(Vec<(usize, usize)>);
+
+        impl Visitor for Loops {
+            fn visit_loop(&mut self, index: usize, body: &Block) {
+                self.0.push((index, body.len()));
+                walk_loop(self, index, body);
+            }
+        }
+
+        // MOO MoO MOO MOo moo moO moo MOO OOO moo
+        let program = [
+            BeginLoop,
+            IncrementByte,
+            BeginLoop,
+            DecrementByte,
+            EndLoop,
+            IncrementPointer,
+            EndLoop,
+            BeginLoop,
+            SetZero,
+            EndLoop,
+        ];
+        let mut loops = Loops(vec![]);
+        loops.visit_block(0, &parse(&program));
+
+        assert_eq!(loops.0, [(0, 5), (2, 1), (7, 1)]);
+    }
+
+    #[test]
+    fn fold_works() {
+        /// Removes loops which only decrement.
+        struct Unwrap;
+
+        impl Fold for Unwrap {
+            fn fold_loop(&mut self, body: Block) -> Node {
+                let body = self.fold_block(body);
+                match body.0.as_slice() {
+                    [Node::Op(DecrementByte)] => Node::Op(SetZero),
+                    _ => Node::Loop { body },
+                }
+            }
+        }
+
+        // MoO MOO moO MOO MOo moo mOo moo
+        let program = [
+            IncrementByte,
+            BeginLoop,
+            IncrementPointer,
+            BeginLoop,
+            DecrementByte,
+            EndLoop,
+            DecrementPointer,
+            EndLoop,
+        ];
+
+        assert_eq!(
+            Unwrap.fold_block(parse(&program)).to_program(),
+            [
+                IncrementByte,
+                BeginLoop,
+                IncrementPointer,
+                SetZero,
+                DecrementPointer,
+                EndLoop,
+            ]
+        );
+    }
+
+    #[test]
+    fn fold_pads_emptied_loops() {
+        /// Removes every `moO`.
+        struct Remove;
+
+        impl Fold for Remove {
+            fn fold_block(&mut self, block: Block) -> Block {
+                Block(
+                    block
+                        .0
+                        .into_iter()
+                        .filter(|node| *node != Node::Op(IncrementPointer))
+                        .map(|node| self.fold_node(node))
+                        .collect(),
+                )
+            }
+        }
+
+        // MOO moO moo MoO OOM
+        let program = [
+            BeginLoop,
+            IncrementPointer,
+            EndLoop,
+            IncrementByte,
+            WriteStdout,
+        ];
+        let folded = Remove.fold_block(parse(&program)).to_program();
+
+        assert_eq!(
+            folded,
+            [
+                BeginLoop,
+                IncrementByte,
+                DecrementByte,
+                EndLoop,
+                IncrementByte,
+                WriteStdout,
+            ]
+        );
+        let mut output = vec![];
+        Interpreter::new(folded)
+            .run_with(&mut &b""[..], &mut output)
+            .unwrap();
+        assert_eq!(output, b"1");
+    }
+
+    #[test]
+    fn to_program_pads_nested_loops() {
+        let block = Block(vec![Node::Loop {
+            body: Block(vec![Node::Loop {
+                body: Block(vec![Node::Op(DecrementByte)]),
+            }]),
+        }]);
+        let program = block.to_program();
+
+        assert_eq!(
+            program,
+            [
+                BeginLoop,
+                IncrementByte,
+                DecrementByte,
+                BeginLoop,
+                DecrementByte,
+                EndLoop,
+                IncrementByte,
+                DecrementByte,
+                EndLoop,
+            ]
+        );
+        assert!(parse(&program).is_structured());
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn to_program_reverses_parse(codes in proptest::collection::vec(0..12, 0..64)) {
+            use crate::instruction::AsInstruction;
+
+            let program: Vec<_> = codes
+                .into_iter()
+                .filter_map(|code: i32| code.as_instruction())
+                .collect();
+            proptest::prop_assert_eq!(parse(&program).to_program(), program);
+        }
+    }
+}
@@ -0,0 +1,385 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::{
+    instruction::{
+        AsInstruction,
+        Instruction::{self, *},
+    },
+    translate::{find_begin_loop, find_end_loop},
+};
+
+/// Times a loop head is updated before its interval is widened to the whole tape.
+const WIDENING_DELAY: usize = 3;
+
+/// Possible values of the pointer, both inclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interval {
+    pub low: usize,
+    pub high: usize,
+}
+
+impl Interval {
+    fn join(self, other: Self) -> Self {
+        Self {
+            low: self.low.min(other.low),
+            high: self.high.max(other.high),
+        }
+    }
+}
+
+/// A pointer move which may take the pointer off the tape.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    /// Index of `moO`, `mOo` or `mOO`.
+    pub index: usize,
+    pub instruction: Instruction,
+    /// The pointer right before the instruction.
+    pub pointer: Interval,
+    /// `true` if it leaves the tape whenever it runs.
+    pub certain: bool,
+    /// `true` if it may only leave the tape because of a loop in [`Bounds::widened`].
+    pub widened: bool,
+}
+
+/// The result of [`analyze`].
+#[derive(Debug, Clone)]
+pub struct Bounds {
+    /// The pointer before each instruction, or `None` if the instruction is never reached.
+    pub pointers: Vec<Option<Interval>>,
+    pub violations: Vec<Violation>,
+    /// Instructions where the pointer was given up on, which are the targets of loops that move
+    /// the pointer by an unknown amount.
+    pub widened: Vec<usize>,
+}
+
+impl Bounds {
+    /// Returns `true` if the pointer never leaves the tape.
+    ///
+    /// The interpreter still checks the pointer on every move: the proof only holds for a
+    /// pointer starting at block 0, which isn't the case after
+    /// [`Interpreter::load`](crate::interpreter::Interpreter::load) in the REPL or
+    /// [`Interpreter::restore`](crate::interpreter::Interpreter::restore), and a comparison per
+    /// move costs less than analyzing every program before running it.
+    pub fn is_proven(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+/// Finds where the pointer may leave a tape of `memory_size` blocks, starting at block 0.
+///
+/// Follows every jump the interpreter could make, including those of `MOO`s and `moo`s which
+/// aren't well nested, with the pointer tracked as an interval. `mOO` is assumed to run any
+/// instruction. Since the interpreter stops as soon as the pointer would leave the tape, the
+/// intervals never grow past it, which keeps loops moving the pointer from growing forever.
+pub fn analyze(program: &[Instruction], memory_size: usize) -> Bounds {
+    analyze_with(program, memory_size, &HashMap::new())
+}
+
+/// Like [`analyze`], with what some `mOO`s are known to run, by index: the instruction, or
+/// `None` if they stop the program with an error. The other `mOO`s may run any instruction.
+pub fn analyze_with(
+    program: &[Instruction],
+    memory_size: usize,
+    executes: &HashMap<usize, Option<Instruction>>,
+) -> Bounds {
+    let last = memory_size.saturating_sub(1);
+    let mut pointers: Vec<Option<Pointer>> = vec![None; program.len() + 1];
+    let mut updates = vec![0; program.len() + 1];
+    let mut violations: Vec<Violation> = vec![];
+    let mut widened = vec![];
+    let mut queue = VecDeque::new();
+
+    pointers[0] = Some(Pointer {
+        interval: Interval { low: 0, high: 0 },
+        widened: (false, false),
+    });
+    queue.push_back(0);
+
+    while let Some(index) = queue.pop_front() {
+        let Some(pointer) = pointers[index] else {
+            continue;
+        };
+        let Some(&instruction) = program.get(index) else {
+            continue;
+        };
+        let Interval { low, high } = pointer.interval;
+
+        let candidates: Vec<Instruction> = match (instruction, executes.get(&index)) {
+            (ExecuteValue, Some(executed)) => executed.iter().copied().collect(),
+            (ExecuteValue, None) => (0..12)
+                .filter_map(|code: i32| code.as_instruction())
+                .filter(|&instruction| instruction != ExecuteValue)
+                .collect(),
+            _ => vec![instruction],
+        };
+        let mut successors = vec![];
+        let (mut may_leave, mut always_leaves, mut given_up) = (false, true, true);
+        for candidate in candidates {
+            match candidate {
+                IncrementPointer | DecrementPointer => {
+                    let forward = candidate == IncrementPointer;
+                    let (leaves, stays) = if forward {
+                        (high == last, low < last)
+                    } else {
+                        (low == 0, high > 0)
+                    };
+                    if leaves {
+                        may_leave = true;
+                        given_up &= if forward {
+                            pointer.widened.1
+                        } else {
+                            pointer.widened.0
+                        };
+                    }
+                    always_leaves &= !stays;
+                    if stays {
+                        let interval = if forward {
+                            Interval {
+                                low: low + 1,
+                                high: high.min(last - 1) + 1,
+                            }
+                        } else {
+                            Interval {
+                                low: low.max(1) - 1,
+                                high: high - 1,
+                            }
+                        };
+                        successors.push((
+                            index + 1,
+                            Pointer {
+                                interval,
+                                ..pointer
+                            },
+                        ));
+                    }
+                }
+                BeginLoop => {
+                    always_leaves = false;
+                    successors.push((index + 1, pointer));
+                    if let Some(end) = find_end_loop(program, index) {
+                        successors.push((end + 1, pointer));
+                    }
+                }
+                EndLoop => {
+                    always_leaves = false;
+                    successors.push((index + 1, pointer));
+                    if let Some(begin) = find_begin_loop(program, index) {
+                        successors.push((begin + 1, pointer));
+                    }
+                }
+                _ => {
+                    always_leaves = false;
+                    successors.push((index + 1, pointer));
+                }
+            }
+        }
+
+        if may_leave {
+            let violation = Violation {
+                index,
+                instruction,
+                pointer: pointer.interval,
+                certain: always_leaves,
+                widened: given_up,
+            };
+            match violations
+                .iter_mut()
+                .find(|violation| violation.index == index)
+            {
+                Some(existing) => *existing = violation,
+                None => violations.push(violation),
+            }
+        }
+
+        for (successor, pointer) in successors {
+            let Some(existing) = pointers[successor] else {
+                pointers[successor] = Some(pointer);
+                queue.push_back(successor);
+                continue;
+            };
+            let mut joined = existing.join(pointer);
+            if joined == existing {
+                continue;
+            }
+            // Only loop heads are widened, which is enough to stop every loop from growing.
+            updates[successor] += 1;
+            if successor <= index && updates[successor] > WIDENING_DELAY {
+                if joined.interval.low < existing.interval.low {
+                    joined.interval.low = 0;
+                    joined.widened.0 = true;
+                }
+                if joined.interval.high > existing.interval.high {
+                    joined.interval.high = last;
+                    joined.widened.1 = true;
+                }
+                if !widened.contains(&successor) {
+                    widened.push(successor);
+                }
+            }
+            pointers[successor] = Some(joined);
+            queue.push_back(successor);
+        }
+    }
+
+    violations.sort_by_key(|violation| violation.index);
+    widened.sort_unstable();
+    Bounds {
+        pointers: pointers[..program.len()]
+            .iter()
+            .map(|pointer| pointer.map(|pointer| pointer.interval))
+            .collect(),
+        violations,
+        widened,
+    }
+}
+
+/// The pointer at a program point.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Pointer {
+    interval: Interval,
+    /// Whether the low and the high ends come from widening, rather than from the program.
+    widened: (bool, bool),
+}
+
+impl Pointer {
+    fn join(self, other: Self) -> Self {
+        let pick =
+            |ours: usize, theirs: usize, flag: bool, other_flag: bool, lower: bool| match ours
+                .cmp(&theirs)
+            {
+                std::cmp::Ordering::Equal => flag || other_flag,
+                std::cmp::Ordering::Less => {
+                    if lower {
+                        flag
+                    } else {
+                        other_flag
+                    }
+                }
+                std::cmp::Ordering::Greater => {
+                    if lower {
+                        other_flag
+                    } else {
+                        flag
+                    }
+                }
+            };
+        Self {
+            interval: self.interval.join(other.interval),
+            widened: (
+                pick(
+                    self.interval.low,
+                    other.interval.low,
+                    self.widened.0,
+                    other.widened.0,
+                    true,
+                ),
+                pick(
+                    self.interval.high,
+                    other.interval.high,
+                    self.widened.1,
+                    other.widened.1,
+                    false,
+                ),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn analyze_proves_balanced_loops() {
+        // moO MoO MOO mOo MoO moO MOo moo mOo
+        let program = [
+            IncrementPointer,
+            IncrementByte,
+            BeginLoop,
+            DecrementPointer,
+            IncrementByte,
+            IncrementPointer,
+            DecrementByte,
+            EndLoop,
+            DecrementPointer,
+        ];
+        let bounds = analyze(&program, 10);
+
+        assert!(bounds.is_proven());
+        assert_eq!(bounds.pointers[4], Some(Interval { low: 0, high: 0 }));
+    }
+
+    #[test]
+    fn analyze_finds_leaving_pointers() {
+        // mOo
+        let bounds = analyze(&[DecrementPointer], 10);
+        assert_eq!(
+            bounds.violations,
+            [Violation {
+                index: 0,
+                instruction: DecrementPointer,
+                pointer: Interval { low: 0, high: 0 },
+                certain: true,
+                widened: false,
+            }]
+        );
+
+        // MoO MOO moO MoO moo - moves right until the end of the tape
+        let program = [
+            IncrementByte,
+            BeginLoop,
+            IncrementPointer,
+            IncrementByte,
+            EndLoop,
+        ];
+        let bounds = analyze(&program, 10);
+        assert_eq!(
+            bounds.violations,
+            [Violation {
+                index: 2,
+                instruction: IncrementPointer,
+                pointer: Interval { low: 0, high: 9 },
+                certain: false,
+                widened: true,
+            }]
+        );
+    }
+
+    #[test]
+    fn analyze_follows_execute_value() {
+        // moO mOO
+        let bounds = analyze(&[IncrementPointer, ExecuteValue], 10);
+        assert!(bounds.is_proven());
+
+        // mOO
+        assert!(!analyze(&[ExecuteValue], 10).is_proven());
+    }
+
+    #[test]
+    fn analyze_with_uses_known_executes() {
+        // mOO running `MoO`, then with 3
+        let executes = HashMap::from([(0, Some(IncrementByte)), (1, None)]);
+        let bounds = analyze_with(
+            &[ExecuteValue, ExecuteValue, DecrementPointer],
+            10,
+            &executes,
+        );
+        assert!(bounds.is_proven());
+        assert_eq!(bounds.pointers[2], None);
+
+        // mOO running `mOo`
+        let executes = HashMap::from([(0, Some(DecrementPointer))]);
+        let bounds = analyze_with(&[ExecuteValue], 10, &executes);
+        assert!(bounds.violations[0].certain);
+    }
+
+    #[test]
+    fn analyze_samples() {
+        let lexer =
+            crate::lexer::Lexer::from_bytes(include_bytes!("../samples/hello_world.cow").to_vec())
+                .comment("[[", "]]");
+        let program = lexer.lex().unwrap();
+
+        assert!(analyze(&program, 30000).is_proven());
+    }
+}
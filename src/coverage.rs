@@ -0,0 +1,376 @@
+use std::{collections::BTreeMap, fmt::Write, path::Path};
+
+use anyhow::{ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    instruction::{self, Instruction},
+    interpreter::Interpreter,
+    observer::Observer,
+};
+
+/// Which instructions and loop branches ran, over one or more runs of a program. It's recorded
+/// as an [`Observer`].
+///
+/// It's saved as JSON so that runs with different inputs add up, and rendered as lcov or as
+/// an annotated source. Only `MOO` run as an instruction counts as a branch, not run by `mOO`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Coverage {
+    /// Hash of the program, so that only coverage of the same program is merged.
+    hash: u64,
+    /// Number of runs merged.
+    runs: u64,
+    /// Executions of each instruction.
+    counts: Vec<u64>,
+    /// Branches of each `MOO`, by index.
+    branches: BTreeMap<usize, Branches>,
+}
+
+/// How often a `MOO` went each way.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Branches {
+    /// Number of times the memory block wasn't 0, so that the loop was entered.
+    pub entered: u64,
+    /// Number of times the memory block was 0, so that the loop was skipped.
+    pub skipped: u64,
+}
+
+/// The coverage of a line of the source.
+#[derive(Debug, Clone, PartialEq)]
+struct Line {
+    /// 1-based.
+    number: usize,
+    text: String,
+    /// Executions of the instructions on the line.
+    counts: Vec<u64>,
+    /// Branches of the `MOO`s on the line, with their columns.
+    branches: Vec<(usize, Branches)>,
+}
+
+impl Line {
+    /// Number of times the whole line ran, or `None` if it has no instructions.
+    fn count(&self) -> Option<u64> {
+        self.counts.iter().min().copied()
+    }
+
+    /// Returns `true` if some instructions of the line ran but not all of them.
+    fn is_partial(&self) -> bool {
+        self.count() == Some(0) && self.counts.iter().any(|&count| count > 0)
+    }
+}
+
+impl Coverage {
+    pub fn new(program: &[Instruction]) -> Self {
+        let branches = program
+            .iter()
+            .enumerate()
+            .filter(|&(_, &instruction)| instruction == Instruction::BeginLoop)
+            .map(|(index, _)| (index, Branches::default()))
+            .collect();
+        Self {
+            hash: instruction::hash(program),
+            runs: 1,
+            counts: vec![0; program.len()],
+            branches,
+        }
+    }
+
+    /// Reads coverage saved with [`Coverage::save`].
+    pub fn load(path: &Path) -> Result<Self> {
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read `{}`", path.display()))?;
+        serde_json::from_str(&json)
+            .with_context(|| format!("`{}` isn't coverage data", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, serde_json::to_string(self)?)
+            .with_context(|| format!("Failed to write `{}`", path.display()))
+    }
+
+    /// Counts the instruction at `index`, about to run with `value` in the current memory block.
+    fn count(&mut self, index: usize, instruction: Instruction, value: i32) {
+        self.counts[index] += 1;
+        if instruction == Instruction::BeginLoop {
+            let branches = self.branches.entry(index).or_default();
+            if value == 0 {
+                branches.skipped += 1;
+            } else {
+                branches.entered += 1;
+            }
+        }
+    }
+
+    /// Adds the runs of `other`, which must be of the same program.
+    pub fn merge(&mut self, other: &Coverage) -> Result<()> {
+        ensure!(
+            self.hash == other.hash && self.counts.len() == other.counts.len(),
+            "The coverage data is of another program"
+        );
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other;
+        }
+        for (&index, other) in &other.branches {
+            let branches = self.branches.entry(index).or_default();
+            branches.entered += other.entered;
+            branches.skipped += other.skipped;
+        }
+        self.runs += other.runs;
+        Ok(())
+    }
+
+    /// Returns `true` if the coverage is of `program`.
+    pub fn is_of(&self, program: &[Instruction]) -> bool {
+        self.hash == instruction::hash(program) && self.counts.len() == program.len()
+    }
+
+    /// Number of runs merged.
+    pub fn runs(&self) -> u64 {
+        self.runs
+    }
+
+    /// Executions of each instruction, by index.
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    /// Branches of each `MOO`, by index.
+    pub fn branches(&self) -> &BTreeMap<usize, Branches> {
+        &self.branches
+    }
+
+    /// Renders the coverage in the lcov tracefile format. A line is hit as many times as its
+    /// least run instruction, and each `MOO` has two branches: entering and skipping the loop.
+    pub fn lcov(&self, source_path: &Path, source: &[u8], positions: &[(usize, usize)]) -> String {
+        let lines = self.lines(source, positions);
+        let mut lcov = format!("TN:\nSF:{}\n", source_path.display());
+        let mut found = 0;
+        let mut hit = 0;
+        for line in &lines {
+            for (column, branches) in &line.branches {
+                let ran = branches.entered + branches.skipped > 0;
+                let counts = [branches.entered, branches.skipped];
+                for (branch, taken) in counts.into_iter().enumerate() {
+                    let taken = if ran { taken.to_string() } else { "-".into() };
+                    writeln!(lcov, "BRDA:{},{column},{branch},{taken}", line.number).unwrap();
+                }
+                found += 2;
+                hit += (branches.entered > 0) as usize + (branches.skipped > 0) as usize;
+            }
+        }
+        writeln!(lcov, "BRF:{found}\nBRH:{hit}").unwrap();
+
+        let (mut found, mut hit) = (0, 0);
+        for line in &lines {
+            if let Some(count) = line.count() {
+                writeln!(lcov, "DA:{},{count}", line.number).unwrap();
+                found += 1;
+                hit += (count > 0) as usize;
+            }
+        }
+        writeln!(lcov, "LF:{found}\nLH:{hit}\nend_of_record").unwrap();
+        lcov
+    }
+
+    /// Renders the source with the number of times each line ran in the margin: `#####` for
+    /// lines which never ran, `partial` for lines which only ran in part, and `-` for lines
+    /// without instructions. The branches of each `MOO` follow its line.
+    pub fn text(&self, source: &[u8], positions: &[(usize, usize)]) -> String {
+        let mut text = self.summary();
+        text.push('\n');
+        for line in self.lines(source, positions) {
+            let count = match line.count() {
+                None => "-".into(),
+                Some(0) if line.is_partial() => "partial".into(),
+                Some(0) => "#####".into(),
+                Some(count) => count.to_string(),
+            };
+            writeln!(text, "{count:>9} | {}", line.text).unwrap();
+            for (column, branches) in &line.branches {
+                writeln!(
+                    text,
+                    "{:>9} | MOO at {}:{column} entered {} times, skipped {} times",
+                    "branch", line.number, branches.entered, branches.skipped
+                )
+                .unwrap();
+            }
+        }
+        text
+    }
+
+    /// Renders the source as a standalone HTML page, with covered lines in green, partially
+    /// covered ones in yellow and lines which never ran in red.
+    pub fn html(&self, title: &str, source: &[u8], positions: &[(usize, usize)]) -> String {
+        let mut html = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n\
+             <style>\n\
+             body {{ font-family: monospace; }}\n\
+             table {{ border-collapse: collapse; }}\n\
+             td {{ padding: 0 0.5em; white-space: pre; }}\n\
+             .count {{ text-align: right; color: #666; }}\n\
+             .covered {{ background: #dfd; }}\n\
+             .partial {{ background: #ffd; }}\n\
+             .uncovered {{ background: #fdd; }}\n\
+             </style>\n</head>\n<body>\n<h1>{}</h1>\n<p>{}</p>\n<table>\n",
+            escape(title),
+            escape(title),
+            escape(&self.summary()).replace('\n', "<br>\n"),
+        );
+        for line in self.lines(source, positions) {
+            let (class, count) = match line.count() {
+                None => ("", String::new()),
+                Some(0) if line.is_partial() => ("partial", "0".into()),
+                Some(0) => ("uncovered", "0".into()),
+                Some(count) => ("covered", count.to_string()),
+            };
+            let mut title = String::new();
+            for (column, branches) in &line.branches {
+                writeln!(
+                    title,
+                    "MOO at {}:{column} entered {} times, skipped {} times",
+                    line.number, branches.entered, branches.skipped
+                )
+                .unwrap();
+            }
+            writeln!(
+                html,
+                "<tr class=\"{class}\" title=\"{}\"><td class=\"count\">{}</td>\
+                 <td class=\"count\">{count}</td><td>{}</td></tr>",
+                escape(title.trim_end()),
+                line.number,
+                escape(&line.text)
+            )
+            .unwrap();
+        }
+        html.push_str("</table>\n</body>\n</html>\n");
+        html
+    }
+
+    /// Describes how much of the program ran.
+    fn summary(&self) -> String {
+        let ran = self.counts.iter().filter(|&&count| count > 0).count();
+        let branches = self.branches.len() * 2;
+        let taken: usize = self
+            .branches
+            .values()
+            .map(|branches| (branches.entered > 0) as usize + (branches.skipped > 0) as usize)
+            .sum();
+        let percent = |part: usize, whole: usize| 100.0 * part as f64 / whole.max(1) as f64;
+        format!(
+            "{} run(s)\ninstructions: {ran}/{} ({:.1}%)\nbranches: {taken}/{branches} ({:.1}%)\n",
+            self.runs,
+            self.counts.len(),
+            percent(ran, self.counts.len()),
+            percent(taken, branches),
+        )
+    }
+
+    /// Splits `source` into lines along with the coverage of their instructions, placed with
+    /// `positions`.
+    fn lines(&self, source: &[u8], positions: &[(usize, usize)]) -> Vec<Line> {
+        let mut lines: Vec<_> = source
+            .split(|&byte| byte == b'\n')
+            .enumerate()
+            .map(|(index, text)| Line {
+                number: index + 1,
+                text: String::from_utf8_lossy(text).trim_end().to_string(),
+                counts: vec![],
+                branches: vec![],
+            })
+            .collect();
+        if source.ends_with(b"\n") {
+            lines.pop();
+        }
+        for (index, &(number, column)) in positions.iter().enumerate() {
+            let Some(line) = lines.get_mut(number - 1) else {
+                continue;
+            };
+            line.counts.push(self.counts[index]);
+            if let Some(&branches) = self.branches.get(&index) {
+                line.branches.push((column, branches));
+            }
+        }
+        lines
+    }
+}
+
+impl Observer for Coverage {
+    fn before_step(&mut self, interpreter: &Interpreter) {
+        let index = interpreter.program_counter();
+        let value = interpreter.memory()[interpreter.pointer()];
+        self.count(index, interpreter.program()[index], value);
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::instruction::Instruction::*;
+
+    #[test]
+    fn coverage_works() {
+        // oom
+        // MOO MOo moo
+        // MOO OOM moo
+        let source = b"oom\nMOO MOo moo\n\nMOO OOM moo\n";
+        let positions = [(1, 1), (2, 1), (2, 5), (2, 9), (4, 1), (4, 5), (4, 9)];
+        let program = vec![
+            ReadStdin,
+            BeginLoop,
+            DecrementByte,
+            EndLoop,
+            BeginLoop,
+            WriteStdout,
+            EndLoop,
+        ];
+        let run = |input: &[u8]| {
+            let coverage = Arc::new(Mutex::new(Coverage::new(&program)));
+            let mut interpreter = Interpreter::new(program.clone()).observe(coverage.clone());
+            interpreter.run_with(&mut &input[..], &mut vec![]).unwrap();
+            let coverage = coverage.lock().unwrap().clone();
+            coverage
+        };
+
+        let mut coverage = run(b"0\n");
+        assert_eq!(coverage.counts(), [1, 1, 0, 0, 1, 0, 0]);
+        assert_eq!(
+            coverage.branches()[&1],
+            Branches {
+                entered: 0,
+                skipped: 1
+            }
+        );
+
+        coverage.merge(&run(b"2\n")).unwrap();
+        assert_eq!(coverage.runs(), 2);
+        assert_eq!(coverage.counts(), [2, 2, 2, 2, 2, 0, 0]);
+        assert!(coverage.is_of(&program));
+        assert!(coverage.merge(&Coverage::new(&program[1..])).is_err());
+
+        assert_eq!(
+            coverage.lcov(Path::new("loops.cow"), source, &positions),
+            "TN:\nSF:loops.cow\n\
+             BRDA:2,1,0,1\nBRDA:2,1,1,1\nBRDA:4,1,0,0\nBRDA:4,1,1,2\nBRF:4\nBRH:3\n\
+             DA:1,2\nDA:2,2\nDA:4,0\nLF:3\nLH:2\nend_of_record\n"
+        );
+
+        let text = coverage.text(source, &positions);
+        assert!(text.starts_with("2 run(s)\ninstructions: 5/7 (71.4%)\nbranches: 3/4 (75.0%)\n"));
+        assert!(text.contains("        2 | MOO MOo moo\n"));
+        assert!(text.contains("        - | \n"));
+        assert!(text.contains("  partial | MOO OOM moo\n"));
+        assert!(text.contains("   branch | MOO at 4:1 entered 0 times, skipped 2 times\n"));
+
+        let html = coverage.html("loops.cow", source, &positions);
+        assert!(html.contains("<tr class=\"partial\""));
+    }
+}
@@ -0,0 +1,543 @@
+use std::{
+    io::{BufRead, Write},
+    path::PathBuf,
+    sync::mpsc::{self, TryRecvError},
+};
+
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+
+use crate::{
+    debugger::{Debugger, Point, Resume, Stop},
+    dialect::Dialect,
+    interpreter::MEMORY_SIZE,
+    lexer::Lexer,
+    message::{read_message, write_message},
+};
+
+/// The only thread of a COW program.
+const THREAD_ID: i64 = 1;
+
+/// Variables reference of the machine state: the pointer, the register and so on.
+const MACHINE: i64 = 1;
+
+/// Variables reference of the memory blocks.
+const MEMORY: i64 = 2;
+
+/// Number of steps run between two looks at the requests while the program runs.
+const SLICE: u64 = 100_000;
+
+/// A Debug Adapter Protocol server running one program.
+///
+/// Each stack frame stands for a loop around the current instruction, innermost first, under
+/// the frame of the instruction itself. The program reads its input from the `input` launch
+/// argument and its output is sent as `output` events.
+///
+/// The program runs in slices of steps, so that requests such as `pause` are handled while it
+/// runs and its output is sent as it comes.
+pub struct Server<W> {
+    /// Lexes the program, with the options of the session.
+    lexer: Lexer,
+    /// Whether the dialect is guessed from the extension of the program instead of taken from
+    /// `lexer`.
+    guess_dialect: bool,
+    output: W,
+    seq: i64,
+    debugger: Option<Debugger>,
+    source: PathBuf,
+    stop_on_entry: bool,
+    /// What the program is running for, if it's running.
+    running: Option<Resume>,
+}
+
+impl<W: Write> Server<W> {
+    pub fn new(lexer: Lexer, output: W) -> Self {
+        Self {
+            lexer,
+            guess_dialect: false,
+            output,
+            seq: 0,
+            debugger: None,
+            source: PathBuf::new(),
+            stop_on_entry: false,
+            running: None,
+        }
+    }
+
+    /// Guesses the dialect of the program from its extension, see [`Dialect::guess`].
+    pub fn guess_dialect(mut self, guess: bool) -> Self {
+        self.guess_dialect = guess;
+        self
+    }
+
+    /// Handles requests from `input` until the client disconnects.
+    ///
+    /// `input` is read on another thread, which is left blocked on it if the client disconnects
+    /// without closing it.
+    pub fn serve<R: BufRead + Send + 'static>(&mut self, mut input: R) -> Result<()> {
+        let (sender, requests) = mpsc::channel();
+        std::thread::spawn(move || loop {
+            let request = read_message(&mut input);
+            let end = !matches!(request, Ok(Some(_)));
+            if sender.send(request).is_err() || end {
+                break;
+            }
+        });
+
+        loop {
+            let request = match self.running {
+                Some(resume) => match requests.try_recv() {
+                    Ok(request) => request,
+                    Err(TryRecvError::Empty) => {
+                        self.resume(resume)?;
+                        continue;
+                    }
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                },
+                None => match requests.recv() {
+                    Ok(request) => request,
+                    Err(_) => return Ok(()),
+                },
+            };
+            let Some(request) = request? else {
+                return Ok(());
+            };
+            if !self.request(&request)? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Handles a request and responds to it. Returns `false` if the client disconnected.
+    fn request(&mut self, request: &Value) -> Result<bool> {
+        let command = request["command"].as_str().unwrap_or_default().to_string();
+        let arguments = request.get("arguments").cloned().unwrap_or(Value::Null);
+        log::debug!("DAP request `{command}`: {arguments}");
+
+        let (success, body, message) = match self.handle(&command, &arguments) {
+            Ok(body) => (true, body, None),
+            Err(e) => (false, Value::Null, Some(format!("{e:#}"))),
+        };
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "success": success,
+            "command": command,
+            "body": body,
+        });
+        if let Some(message) = message {
+            response["message"] = message.into();
+        }
+        self.send(response)?;
+
+        match command.as_str() {
+            "initialize" => self.event("initialized", json!({}))?,
+            "configurationDone" if success => {
+                if self.stop_on_entry {
+                    self.stopped("entry", None, vec![])?;
+                } else {
+                    self.running = Some(Resume::Continue);
+                    self.resume(Resume::Continue)?;
+                }
+            }
+            "continue" | "next" | "stepIn" | "stepOut" | "stepBack" | "reverseContinue"
+                if success =>
+            {
+                let resume = match command.as_str() {
+                    "continue" => Resume::Continue,
+                    "next" => Resume::Next,
+                    "stepIn" => Resume::Step,
+                    "stepOut" => Resume::Finish,
+                    "stepBack" => Resume::ReverseStep,
+                    _ => Resume::ReverseContinue,
+                };
+                self.running = Some(resume);
+                self.resume(resume)?;
+            }
+            "pause" if success => {
+                self.running = None;
+                self.stopped("pause", None, vec![])?;
+            }
+            "disconnect" | "terminate" => return Ok(false),
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    /// Handles a request, returning the body of the response.
+    fn handle(&mut self, command: &str, arguments: &Value) -> Result<Value> {
+        match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsTerminateRequest": true,
+                "supportsStepBack": true,
+            })),
+            "launch" => {
+                let program = arguments["program"]
+                    .as_str()
+                    .context("The `program` launch argument is missing")?;
+                let source = PathBuf::from(program);
+                let bytes = std::fs::read(&source)
+                    .with_context(|| format!("Failed to read `{}`", source.display()))?;
+                let mut lexer = self.lexer.with_source(bytes);
+                if self.guess_dialect {
+                    lexer = lexer.dialect(Dialect::guess(&source));
+                }
+                let positions = lexer.instruction_positions();
+                let mut debugger = Debugger::new(lexer.lex()?, positions);
+                if let Some(input) = arguments["input"].as_str() {
+                    debugger.interpreter_mut().push_input(input.as_bytes());
+                }
+                self.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or_default();
+                self.source = source.canonicalize().unwrap_or(source);
+                self.debugger = Some(debugger);
+                Ok(Value::Null)
+            }
+            "setBreakpoints" => {
+                let debugger = self.debugger.as_mut().context("No program was launched")?;
+                let ids: Vec<_> = debugger
+                    .points()
+                    .iter()
+                    .filter(|(_, point)| matches!(point, Point::Break(_)))
+                    .map(|&(id, _)| id)
+                    .collect();
+                for id in ids {
+                    debugger.remove(id)?;
+                }
+
+                let lines = arguments["breakpoints"].as_array().cloned();
+                let mut breakpoints = vec![];
+                for breakpoint in lines.unwrap_or_default() {
+                    let line = breakpoint["line"].as_u64().unwrap_or_default() as usize;
+                    let index = debugger.index_of_line(line);
+                    let breakpoint = match index {
+                        Some(index) => {
+                            let id = debugger.add(Point::Break(index))?;
+                            let (line, column) = debugger.position(index).unwrap_or((line, 1));
+                            json!({ "id": id, "verified": true, "line": line, "column": column })
+                        }
+                        None => json!({
+                            "verified": false,
+                            "line": line,
+                            "message": "No instruction on this line or after",
+                        }),
+                    };
+                    breakpoints.push(breakpoint);
+                }
+                Ok(json!({ "breakpoints": breakpoints }))
+            }
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] })),
+            "stackTrace" => {
+                let debugger = self.debugger.as_ref().context("No program was launched")?;
+                let counter = debugger.interpreter().program_counter();
+                let frame = |id: usize, name: String, index: usize| {
+                    let (line, column) = debugger.position(index).unwrap_or((0, 0));
+                    json!({
+                        "id": id,
+                        "name": name,
+                        "source": self.source(),
+                        "line": line,
+                        "column": column,
+                    })
+                };
+
+                let mut frames = vec![frame(0, debugger.describe(counter), counter)];
+                for (id, range) in debugger.loops_around(counter).into_iter().enumerate() {
+                    let name = format!("loop {}", debugger.describe(range.start));
+                    frames.push(frame(id + 1, name, range.start));
+                }
+                Ok(json!({ "stackFrames": frames, "totalFrames": frames.len() }))
+            }
+            "scopes" => Ok(json!({
+                "scopes": [
+                    { "name": "Machine", "variablesReference": MACHINE, "expensive": false },
+                    {
+                        "name": "Memory",
+                        "variablesReference": MEMORY,
+                        "indexedVariables": MEMORY_SIZE,
+                        "expensive": false,
+                    },
+                ],
+            })),
+            "variables" => {
+                let debugger = self.debugger.as_ref().context("No program was launched")?;
+                let interpreter = debugger.interpreter();
+                let variable = |name: String, value: String| {
+                    json!({
+                        "name": name,
+                        "value": value,
+                        "variablesReference": 0,
+                    })
+                };
+                let variables = match arguments["variablesReference"].as_i64() {
+                    Some(MACHINE) => vec![
+                        variable("pointer".into(), interpreter.pointer().to_string()),
+                        variable(
+                            "current block".into(),
+                            interpreter.memory()[interpreter.pointer()].to_string(),
+                        ),
+                        variable(
+                            "register".into(),
+                            interpreter
+                                .register()
+                                .map_or("empty".into(), |value| value.to_string()),
+                        ),
+                        variable(
+                            "program counter".into(),
+                            interpreter.program_counter().to_string(),
+                        ),
+                    ],
+                    Some(MEMORY) => {
+                        // Without paging, the blocks up to the last one in use are shown.
+                        let used = interpreter
+                            .memory()
+                            .iter()
+                            .rposition(|&value| value != 0)
+                            .unwrap_or_default()
+                            .max(interpreter.pointer())
+                            + 1;
+                        let start = arguments["start"].as_u64().unwrap_or_default() as usize;
+                        let count = arguments["count"]
+                            .as_u64()
+                            .map_or(used.saturating_sub(start), |count| count as usize);
+                        let end = start.saturating_add(count).min(MEMORY_SIZE);
+                        (start.min(end)..end)
+                            .map(|index| {
+                                variable(
+                                    format!("[{index}]"),
+                                    interpreter.memory()[index].to_string(),
+                                )
+                            })
+                            .collect()
+                    }
+                    _ => bail!("Unknown variables reference"),
+                };
+                Ok(json!({ "variables": variables }))
+            }
+            "continue" => Ok(json!({ "allThreadsContinued": true })),
+            "pause" => {
+                if self.running.is_none() {
+                    bail!("The program isn't running");
+                }
+                Ok(Value::Null)
+            }
+            "next"
+            | "stepIn"
+            | "configurationDone"
+            | "setExceptionBreakpoints"
+            | "disconnect"
+            | "terminate" => Ok(Value::Null),
+            "stepOut" => {
+                let debugger = self.debugger.as_ref().context("No program was launched")?;
+                let counter = debugger.interpreter().program_counter();
+                if debugger.loops_around(counter).is_empty() {
+                    bail!("Not in a loop");
+                }
+                Ok(Value::Null)
+            }
+            _ => bail!("Unsupported request `{command}`"),
+        }
+    }
+
+    /// Runs a slice of the program, and tells the client where it stopped if it did.
+    fn resume(&mut self, resume: Resume) -> Result<()> {
+        let Some(debugger) = &mut self.debugger else {
+            self.running = None;
+            return Ok(());
+        };
+        let mut output = vec![];
+        let stop = debugger.resume_for(resume, SLICE, &mut &b""[..], &mut output);
+        if !output.is_empty() {
+            let output = String::from_utf8_lossy(&output).into_owned();
+            self.event("output", json!({ "category": "stdout", "output": output }))?;
+        }
+        let stop = match stop {
+            Ok(Some(stop)) => stop,
+            Ok(None) => return Ok(()),
+            Err(e) => {
+                self.running = None;
+                return Err(e);
+            }
+        };
+        self.running = None;
+
+        match stop {
+            Stop::Done => self.stopped("step", None, vec![]),
+            Stop::Beginning => self.stopped(
+                "step",
+                Some("Reached the oldest recorded step".into()),
+                vec![],
+            ),
+            Stop::Breakpoint { id } => self.stopped("breakpoint", None, vec![id]),
+            Stop::Watchpoint { id, .. } => self.stopped("data breakpoint", None, vec![id]),
+            Stop::NeedInput => {
+                self.stopped("pause", Some("The program needs input".into()), vec![])
+            }
+            Stop::Error(e) => self.stopped("exception", Some(e.to_string()), vec![]),
+            Stop::Halted => {
+                self.event("exited", json!({ "exitCode": 0 }))?;
+                self.event("terminated", json!({}))
+            }
+        }
+    }
+
+    fn stopped(&mut self, reason: &str, text: Option<String>, ids: Vec<usize>) -> Result<()> {
+        let mut body = json!({
+            "reason": reason,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+            "hitBreakpointIds": ids,
+        });
+        if let Some(text) = text {
+            body["description"] = text.clone().into();
+            body["text"] = text.into();
+        }
+        self.event("stopped", body)
+    }
+
+    fn source(&self) -> Value {
+        let name = self
+            .source
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned());
+        json!({ "name": name, "path": self.source })
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    fn send(&mut self, mut message: Value) -> Result<()> {
+        self.seq += 1;
+        message["seq"] = self.seq.into();
+        write_message(&mut self.output, &message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Serves `requests` for the program in `source`, returning the messages sent back.
+    fn serve(name: &str, source: &str, requests: Vec<Value>) -> Vec<Value> {
+        let path = std::env::temp_dir().join(format!("cowi-{name}-{}.cow", std::process::id()));
+        std::fs::write(&path, source).unwrap();
+
+        let mut input = vec![];
+        for (seq, mut request) in requests.into_iter().enumerate() {
+            request["seq"] = seq.into();
+            request["type"] = "request".into();
+            if request["command"] == "launch" {
+                request["arguments"]["program"] = path.to_string_lossy().into();
+            }
+            write_message(&mut input, &request).unwrap();
+        }
+
+        let mut output = vec![];
+        let mut server = Server::new(Lexer::from_bytes(vec![]), &mut output);
+        server.serve(std::io::Cursor::new(input)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut output = &output[..];
+        let mut messages = vec![];
+        while let Some(message) = read_message(&mut output).unwrap() {
+            messages.push(message);
+        }
+        messages
+    }
+
+    #[test]
+    fn serve_works() {
+        // `OOM` prints 2, then the loop empties block 0 into block 1.
+        let source = "MoO MoO OOM\nMOO\n moO MoO mOo MOo\nmoo\n";
+        let requests = vec![
+            json!({ "command": "initialize", "arguments": {} }),
+            json!({ "command": "launch", "arguments": {} }),
+            json!({
+                "command": "setBreakpoints",
+                "arguments": { "breakpoints": [{ "line": 3 }, { "line": 9 }] },
+            }),
+            json!({ "command": "configurationDone" }),
+            json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+            json!({ "command": "variables", "arguments": { "variablesReference": MEMORY } }),
+            json!({
+                "command": "variables",
+                "arguments": { "variablesReference": MEMORY, "start": 1, "count": u64::MAX },
+            }),
+            json!({ "command": "stepOut", "arguments": { "threadId": 1 } }),
+            json!({ "command": "disconnect" }),
+        ];
+        let messages = serve("dap", source, requests);
+        let find = |kind: &str, name: &str, nth: usize| {
+            messages
+                .iter()
+                .filter(|message| message["type"] == kind)
+                .filter(|message| message["command"] == name || message["event"] == name)
+                .nth(nth)
+                .cloned()
+                .unwrap()
+        };
+
+        let breakpoints = &find("response", "setBreakpoints", 0)["body"]["breakpoints"];
+        assert_eq!(breakpoints[0]["line"], 3);
+        assert_eq!(breakpoints[0]["verified"], true);
+        assert_eq!(breakpoints[1]["verified"], false);
+
+        assert_eq!(find("event", "output", 0)["body"]["output"], "2");
+        assert_eq!(find("event", "stopped", 0)["body"]["reason"], "breakpoint");
+
+        let frames = &find("response", "stackTrace", 0)["body"]["stackFrames"];
+        assert_eq!(frames.as_array().unwrap().len(), 2);
+        assert_eq!(frames[0]["line"], 3);
+        assert_eq!(frames[1]["line"], 2);
+
+        let variables = &find("response", "variables", 0)["body"]["variables"];
+        assert_eq!(
+            variables,
+            &json!([{ "name": "[0]", "value": "2", "variablesReference": 0 }])
+        );
+        let variables = &find("response", "variables", 1)["body"]["variables"];
+        assert_eq!(variables.as_array().unwrap().len(), MEMORY_SIZE - 1);
+
+        // The loop runs twice, so it comes back to the breakpoint before it ends.
+        let stopped = find("event", "stopped", 1);
+        assert_eq!(stopped["body"]["reason"], "breakpoint");
+        assert_eq!(stopped["body"]["hitBreakpointIds"], json!([1]));
+    }
+
+    #[test]
+    fn pause_works() {
+        // The loop prints 1 forever.
+        let source = "MoO MOO OOM moo";
+        let requests = vec![
+            json!({ "command": "initialize", "arguments": {} }),
+            json!({ "command": "pause", "arguments": { "threadId": 1 } }),
+            json!({ "command": "launch", "arguments": {} }),
+            json!({ "command": "configurationDone" }),
+            json!({ "command": "pause", "arguments": { "threadId": 1 } }),
+            json!({ "command": "disconnect" }),
+        ];
+        let messages = serve("dap-pause", source, requests);
+
+        let pauses: Vec<_> = messages
+            .iter()
+            .filter(|message| message["command"] == "pause")
+            .map(|message| message["success"].clone())
+            .collect();
+        assert_eq!(pauses, [false, true]);
+        let output = messages
+            .iter()
+            .position(|message| message["event"] == "output")
+            .unwrap();
+        let stopped = messages
+            .iter()
+            .position(|message| message["event"] == "stopped")
+            .unwrap();
+        assert!(output < stopped);
+        assert_eq!(messages[stopped]["body"]["reason"], "pause");
+        assert!(messages[output]["body"]["output"]
+            .as_str()
+            .unwrap()
+            .starts_with("111"));
+    }
+}
@@ -0,0 +1,747 @@
+use std::{
+    fmt::Write as _,
+    io::{BufRead, Read, Write},
+    ops::Range,
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    ast,
+    instruction::Instruction,
+    interpreter::{Event, Interpreter},
+    view,
+};
+
+/// Number of memory blocks shown on each side of the pointer.
+const RADIUS: usize = 5;
+
+/// Number of instructions shown on each side of the current one by `list`.
+const CONTEXT: usize = 4;
+
+const HELP: &str = "\
+break LINE | #INDEX   stop before the first instruction on LINE, or at instruction INDEX
+watch CELL | register stop when a memory block or the register changes
+delete ID             remove a breakpoint or a watchpoint
+info                  list breakpoints and watchpoints
+step [N]              run N instructions, 1 by default
+next                  run an instruction, or a whole loop when at its `MOO`
+finish                run until the current loop ends
+continue              run until a breakpoint or a watchpoint stops the program
+reverse-step [N]      undo N instructions, 1 by default
+reverse-continue      undo instructions until a breakpoint or a watchpoint stops the program
+last-write CELL       tell which instruction last wrote a memory block
+print [START] [END]   print memory blocks, or those around the pointer without arguments
+list                  print the instructions around the current one
+help                  print this message
+quit                  leave
+An empty line repeats the last command.";
+
+/// Something which stops the program while it runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Point {
+    /// Stops before running the instruction at this index.
+    Break(usize),
+    Watch(Watch),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watch {
+    /// Stops when the memory block at this index changes.
+    Cell(usize),
+    /// Stops when the register changes.
+    Register,
+}
+
+impl Watch {
+    fn value(self, interpreter: &Interpreter) -> Option<i32> {
+        match self {
+            Self::Cell(index) => Some(interpreter.memory()[index]),
+            Self::Register => interpreter.register(),
+        }
+    }
+}
+
+/// How far [`Debugger::resume`] runs the program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resume {
+    /// Runs one instruction.
+    Step,
+    /// Runs one instruction, or the whole loop if the instruction is its `MOO`.
+    Next,
+    /// Runs until the innermost loop around the current instruction ends.
+    Finish,
+    /// Runs until a breakpoint or a watchpoint stops the program.
+    Continue,
+    /// Undoes one instruction.
+    ReverseStep,
+    /// Undoes instructions until a breakpoint or a watchpoint stops the program, in which case
+    /// the program is right before the instruction at the breakpoint or changing the watched
+    /// value.
+    ReverseContinue,
+}
+
+/// Why [`Debugger::resume`] returned.
+#[derive(Debug)]
+pub enum Stop {
+    /// It ran as far as it was asked to.
+    Done,
+    Breakpoint {
+        id: usize,
+    },
+    Watchpoint {
+        id: usize,
+        old: Option<i32>,
+        new: Option<i32>,
+    },
+    /// The program needs input, but there is none left.
+    NeedInput,
+    /// The program ended.
+    Halted,
+    /// Nothing older is recorded, so the program can't go back further.
+    Beginning,
+    /// The program failed. The program counter stays on the failing instruction.
+    Error(anyhow::Error),
+}
+
+/// Runs a program under control, stopping at breakpoints and watchpoints.
+pub struct Debugger {
+    interpreter: Interpreter,
+    /// Line and column of each instruction, if known.
+    positions: Option<Vec<(usize, usize)>>,
+    /// Ranges from `MOO` to right after `moo` of the well-nested loops.
+    loops: Vec<Range<usize>>,
+    /// Breakpoints and watchpoints with their IDs.
+    points: Vec<(usize, Point)>,
+    next_id: usize,
+    /// Last command given to [`Debugger::eval`].
+    last_command: String,
+    /// `true` if the output of the program doesn't end with a newline.
+    open_line: bool,
+    /// The resume which ran out of steps in [`Debugger::resume_for`], with the range it runs in.
+    suspended: Option<(Resume, Option<Range<usize>>)>,
+}
+
+impl Debugger {
+    /// `positions` are the line and the column of each instruction, as found by
+    /// [`Lexer::instruction_positions`](crate::lexer::Lexer::instruction_positions).
+    pub fn new(program: Vec<Instruction>, positions: Option<Vec<(usize, usize)>>) -> Self {
+        let loops = ast::parse(&program).loops(0);
+        Self {
+            interpreter: Interpreter::new(program).record(true),
+            positions,
+            loops,
+            points: vec![],
+            next_id: 1,
+            last_command: String::new(),
+            open_line: false,
+            suspended: None,
+        }
+    }
+
+    pub fn interpreter(&self) -> &Interpreter {
+        &self.interpreter
+    }
+
+    pub fn interpreter_mut(&mut self) -> &mut Interpreter {
+        &mut self.interpreter
+    }
+
+    pub fn points(&self) -> &[(usize, Point)] {
+        &self.points
+    }
+
+    /// Line and column of the instruction at `index`, if known.
+    pub fn position(&self, index: usize) -> Option<(usize, usize)> {
+        self.positions.as_ref()?.get(index).copied()
+    }
+
+    /// Index of the first instruction on `line` or after it.
+    pub fn index_of_line(&self, line: usize) -> Option<usize> {
+        self.positions
+            .as_ref()?
+            .iter()
+            .position(|&(other, _)| other >= line)
+    }
+
+    /// Adds a breakpoint or a watchpoint, returning its ID.
+    pub fn add(&mut self, point: Point) -> Result<usize> {
+        match point {
+            Point::Break(index) if index >= self.interpreter.program().len() => {
+                bail!("There is no instruction {index}")
+            }
+            Point::Watch(Watch::Cell(index)) if index >= self.interpreter.memory().len() => {
+                bail!("There is no memory block {index}")
+            }
+            _ => {}
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.points.push((id, point));
+        Ok(id)
+    }
+
+    /// Removes the breakpoint or the watchpoint with `id`.
+    pub fn remove(&mut self, id: usize) -> Result<Point> {
+        match self.points.iter().position(|&(other, _)| other == id) {
+            Some(index) => Ok(self.points.remove(index).1),
+            None => bail!("There is no breakpoint or watchpoint {id}"),
+        }
+    }
+
+    /// Runs the program as far as `resume` says, or until something stops it.
+    ///
+    /// Input is read line by line from `stdin` when the program needs it, and output is written
+    /// to `stdout`.
+    pub fn resume<R, W>(&mut self, resume: Resume, stdin: &mut R, stdout: &mut W) -> Result<Stop>
+    where
+        R: BufRead + Read,
+        W: Write,
+    {
+        self.suspended = None;
+        let stop = self.resume_for(resume, u64::MAX, stdin, stdout)?;
+        Ok(stop.expect("the program can't run out of steps"))
+    }
+
+    /// Like [`Debugger::resume`], but returns `None` once `steps` instructions ran without
+    /// anything stopping the program, so that the caller can do something else in between.
+    /// Calling it again with the same `resume` goes on as if it hadn't returned.
+    pub fn resume_for<R, W>(
+        &mut self,
+        resume: Resume,
+        steps: u64,
+        stdin: &mut R,
+        stdout: &mut W,
+    ) -> Result<Option<Stop>>
+    where
+        R: BufRead + Read,
+        W: Write,
+    {
+        if matches!(resume, Resume::ReverseStep | Resume::ReverseContinue) {
+            self.suspended = None;
+            return self.reverse(resume).map(Some);
+        }
+        let start = self.interpreter.program_counter();
+        // The program stops as soon as it leaves this range.
+        let range = match self.suspended.take() {
+            Some((suspended, range)) if suspended == resume => range,
+            _ => match resume {
+                Resume::Step | Resume::Continue | Resume::ReverseStep | Resume::ReverseContinue => {
+                    None
+                }
+                Resume::Next => self
+                    .loops
+                    .iter()
+                    .find(|range| range.start == start)
+                    .cloned(),
+                Resume::Finish => match self.innermost_loop(start) {
+                    Some(range) => Some(range.start + 1..range.end),
+                    None => bail!("Not in a loop"),
+                },
+            },
+        };
+        let end = self.interpreter.program().len();
+
+        let mut ran = 0;
+        loop {
+            let counter = self.interpreter.program_counter();
+            if counter >= end {
+                return Ok(Some(Stop::Halted));
+            }
+            if ran > 0 {
+                let left = range
+                    .as_ref()
+                    .is_some_and(|range| !range.contains(&counter));
+                if resume == Resume::Step || (resume != Resume::Continue && range.is_none()) {
+                    return Ok(Some(Stop::Done));
+                }
+                if left {
+                    return Ok(Some(Stop::Done));
+                }
+                let breakpoint = self.points.iter().find(|(_, point)| match point {
+                    Point::Break(index) => *index == counter,
+                    Point::Watch(_) => false,
+                });
+                if let Some(&(id, _)) = breakpoint {
+                    return Ok(Some(Stop::Breakpoint { id }));
+                }
+            }
+            if ran == steps {
+                self.suspended = Some((resume, range));
+                return Ok(None);
+            }
+
+            let watched = self.watched();
+            match self.interpreter.step() {
+                Event::NeedInput => {
+                    let mut line = String::new();
+                    if stdin.read_line(&mut line)? == 0 {
+                        return Ok(Some(Stop::NeedInput));
+                    }
+                    self.interpreter.push_input(line.as_bytes());
+                    continue;
+                }
+                Event::Output(output) => {
+                    self.open_line = output.last() != Some(&b'\n');
+                    stdout.write_all(&output)?;
+                    stdout.flush()?;
+                }
+                Event::Halted => return Ok(Some(Stop::Halted)),
+                Event::Error(e) => return Ok(Some(Stop::Error(e))),
+                Event::Stepped | Event::Jumped { .. } => {}
+            }
+            ran += 1;
+
+            for (id, watch, old) in watched {
+                let new = watch.value(&self.interpreter);
+                if new != old {
+                    return Ok(Some(Stop::Watchpoint { id, old, new }));
+                }
+            }
+        }
+    }
+
+    /// Goes back in the recorded history as far as `resume` says.
+    fn reverse(&mut self, resume: Resume) -> Result<Stop> {
+        loop {
+            let watched = self.watched();
+            let history = self
+                .interpreter
+                .history()
+                .context("The steps aren't recorded")?;
+            if history.steps() == history.oldest() {
+                return Ok(Stop::Beginning);
+            }
+            self.interpreter.step_back()?;
+            if resume == Resume::ReverseStep {
+                return Ok(Stop::Done);
+            }
+
+            for (id, watch, new) in watched {
+                let old = watch.value(&self.interpreter);
+                if new != old {
+                    return Ok(Stop::Watchpoint { id, old, new });
+                }
+            }
+            let counter = self.interpreter.program_counter();
+            if let Some(&(id, _)) = self
+                .points
+                .iter()
+                .find(|&&(_, point)| point == Point::Break(counter))
+            {
+                return Ok(Stop::Breakpoint { id });
+            }
+        }
+    }
+
+    /// Returns the watchpoints with the current values they watch.
+    fn watched(&self) -> Vec<(usize, Watch, Option<i32>)> {
+        self.points
+            .iter()
+            .filter_map(|&(id, point)| match point {
+                Point::Watch(watch) => Some((id, watch, watch.value(&self.interpreter))),
+                Point::Break(_) => None,
+            })
+            .collect()
+    }
+
+    /// Returns the range of the innermost loop whose body or `moo` has `index`.
+    fn innermost_loop(&self, index: usize) -> Option<Range<usize>> {
+        self.loops_around(index).into_iter().next()
+    }
+
+    /// Returns the ranges of the loops whose body or `moo` has `index`, from `MOO` to right
+    /// after `moo`, innermost first.
+    pub fn loops_around(&self, index: usize) -> Vec<Range<usize>> {
+        let mut loops: Vec<_> = self
+            .loops
+            .iter()
+            .filter(|range| range.start < index && index < range.end)
+            .cloned()
+            .collect();
+        loops.sort_by_key(|range| std::cmp::Reverse(range.start));
+        loops
+    }
+
+    /// Describes the instruction at `index`, e.g. `#3 (1:13) MOO`.
+    pub fn describe(&self, index: usize) -> String {
+        let mut description = format!("#{index}");
+        if let Some((line, column)) = self.position(index) {
+            write!(description, " ({line}:{column})").unwrap();
+        }
+        if let Some(instruction) = self.interpreter.program().get(index) {
+            write!(description, " {instruction}").unwrap();
+        }
+        description
+    }
+
+    /// Runs a command of the command-line debugger, writing its result to `stdout`. Returns
+    /// `false` once the user quits.
+    pub fn eval<R, W>(&mut self, line: &str, stdin: &mut R, stdout: &mut W) -> Result<bool>
+    where
+        R: BufRead + Read,
+        W: Write,
+    {
+        let line = match line.trim() {
+            "" => self.last_command.clone(),
+            line => {
+                self.last_command = line.to_string();
+                line.to_string()
+            }
+        };
+        let words: Vec<_> = line.split_whitespace().collect();
+        match words[..] {
+            [] => {}
+            ["q" | "quit"] => return Ok(false),
+            ["h" | "help"] => writeln!(stdout, "{HELP}")?,
+            ["b" | "break", target] => {
+                let index = match target.strip_prefix('#') {
+                    Some(index) => index
+                        .parse()
+                        .with_context(|| format!("`{index}` isn't an instruction index"))?,
+                    None => {
+                        let line = target
+                            .parse()
+                            .with_context(|| format!("`{target}` isn't a line"))?;
+                        self.index_of_line(line)
+                            .with_context(|| format!("No instruction on line {line} or after"))?
+                    }
+                };
+                let id = self.add(Point::Break(index))?;
+                writeln!(stdout, "Breakpoint {id} at {}", self.describe(index))?;
+            }
+            ["w" | "watch", target] => {
+                let watch = match target {
+                    "reg" | "register" => Watch::Register,
+                    cell => Watch::Cell(
+                        cell.parse()
+                            .with_context(|| format!("`{cell}` isn't a memory block"))?,
+                    ),
+                };
+                let id = self.add(Point::Watch(watch))?;
+                writeln!(stdout, "Watchpoint {id} on {}", describe_watch(watch))?;
+            }
+            ["d" | "delete", id] => {
+                let id = id.parse().with_context(|| format!("`{id}` isn't an ID"))?;
+                self.remove(id)?;
+            }
+            ["i" | "info"] => {
+                if self.points.is_empty() {
+                    writeln!(stdout, "No breakpoints or watchpoints")?;
+                }
+                for &(id, point) in &self.points {
+                    match point {
+                        Point::Break(index) => {
+                            writeln!(stdout, "{id}: breakpoint at {}", self.describe(index))?
+                        }
+                        Point::Watch(watch) => {
+                            writeln!(stdout, "{id}: watchpoint on {}", describe_watch(watch))?
+                        }
+                    }
+                }
+            }
+            ["s" | "step"] => self.resume_and_report(Resume::Step, 1, stdin, stdout)?,
+            ["s" | "step", count] => {
+                let count = count
+                    .parse()
+                    .with_context(|| format!("`{count}` isn't a number"))?;
+                self.resume_and_report(Resume::Step, count, stdin, stdout)?
+            }
+            ["n" | "next"] => self.resume_and_report(Resume::Next, 1, stdin, stdout)?,
+            ["f" | "finish"] => self.resume_and_report(Resume::Finish, 1, stdin, stdout)?,
+            ["c" | "continue"] => self.resume_and_report(Resume::Continue, 1, stdin, stdout)?,
+            ["rs" | "reverse-step"] => {
+                self.resume_and_report(Resume::ReverseStep, 1, stdin, stdout)?
+            }
+            ["rs" | "reverse-step", count] => {
+                let count = count
+                    .parse()
+                    .with_context(|| format!("`{count}` isn't a number"))?;
+                self.resume_and_report(Resume::ReverseStep, count, stdin, stdout)?
+            }
+            ["rc" | "reverse-continue"] => {
+                self.resume_and_report(Resume::ReverseContinue, 1, stdin, stdout)?
+            }
+            ["lw" | "last-write", cell] => {
+                let index: usize = cell
+                    .parse()
+                    .with_context(|| format!("`{cell}` isn't a memory block"))?;
+                let history = self
+                    .interpreter
+                    .history()
+                    .context("The steps aren't recorded")?;
+                match history.last_write(index) {
+                    Some(change) => writeln!(
+                        stdout,
+                        "Memory block {index} was last written by step {} at {}, from {}",
+                        change.step,
+                        self.describe(change.program_counter),
+                        change.write.unwrap_or_default()
+                    )?,
+                    None => writeln!(
+                        stdout,
+                        "Memory block {index} wasn't written by the {} steps in the undo log",
+                        history.logged()
+                    )?,
+                }
+            }
+            ["p" | "print"] => writeln!(stdout, "{}", view::tape(&self.interpreter, RADIUS))?,
+            ["p" | "print", ref range @ ..] => {
+                let range = view::parse_range(range, &self.interpreter)?;
+                write!(stdout, "{}", view::table(&self.interpreter, range))?;
+            }
+            ["l" | "list"] => {
+                let counter = self.interpreter.program_counter();
+                let program = self.interpreter.program();
+                let end = (counter + CONTEXT + 1).min(program.len());
+                for index in counter.saturating_sub(CONTEXT)..end {
+                    let marker = if index == counter { "=>" } else { "  " };
+                    writeln!(stdout, "{marker} {}", self.describe(index))?;
+                }
+                if counter >= program.len() {
+                    writeln!(stdout, "=> end of the program")?;
+                }
+            }
+            _ => bail!("Unknown command `{line}`, try `help`"),
+        }
+        Ok(true)
+    }
+
+    /// Resumes the program `count` times, stopping early at a breakpoint, and writes where it
+    /// stopped.
+    fn resume_and_report<R, W>(
+        &mut self,
+        resume: Resume,
+        count: usize,
+        stdin: &mut R,
+        stdout: &mut W,
+    ) -> Result<()>
+    where
+        R: BufRead + Read,
+        W: Write,
+    {
+        let mut stop = Stop::Done;
+        for remaining in (0..count).rev() {
+            stop = self.resume(resume, stdin, stdout)?;
+            if !matches!(stop, Stop::Done) || remaining == 0 {
+                break;
+            }
+            let counter = self.interpreter.program_counter();
+            let breakpoint = self
+                .points
+                .iter()
+                .find(|&&(_, point)| point == Point::Break(counter));
+            if let Some(&(id, _)) = breakpoint {
+                stop = Stop::Breakpoint { id };
+                break;
+            }
+        }
+
+        if std::mem::take(&mut self.open_line) {
+            writeln!(stdout)?;
+        }
+        match stop {
+            Stop::Done => {}
+            Stop::Breakpoint { id } => writeln!(stdout, "Breakpoint {id}")?,
+            Stop::Watchpoint { id, old, new } => {
+                let show = |value: Option<i32>| value.map_or("-".to_string(), |v| v.to_string());
+                writeln!(stdout, "Watchpoint {id}: {} -> {}", show(old), show(new))?;
+            }
+            Stop::NeedInput => writeln!(stdout, "The program needs input, but there is none left")?,
+            Stop::Halted => {
+                writeln!(stdout, "The program ended")?;
+                return Ok(());
+            }
+            Stop::Beginning => writeln!(stdout, "Reached the oldest recorded step")?,
+            Stop::Error(e) => writeln!(stdout, "The program failed: {e}")?,
+        }
+        let counter = self.interpreter.program_counter();
+        writeln!(stdout, "{}", self.describe(counter))?;
+        writeln!(stdout, "{}", view::tape(&self.interpreter, RADIUS))?;
+        Ok(())
+    }
+}
+
+fn describe_watch(watch: Watch) -> String {
+    match watch {
+        Watch::Cell(index) => format!("memory block {index}"),
+        Watch::Register => "the register".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruction::Instruction::*;
+
+    /// MoO MoO MOO moO MoO mOo MOo moo OOM
+    fn debugger() -> Debugger {
+        let program = vec![
+            IncrementByte,
+            IncrementByte,
+            BeginLoop,
+            IncrementPointer,
+            IncrementByte,
+            DecrementPointer,
+            DecrementByte,
+            EndLoop,
+            WriteStdout,
+        ];
+        let positions = (0..program.len()).map(|index| (index + 1, 1)).collect();
+        Debugger::new(program, Some(positions))
+    }
+
+    fn resume(debugger: &mut Debugger, resume: Resume) -> Stop {
+        debugger.resume(resume, &mut &b""[..], &mut vec![]).unwrap()
+    }
+
+    #[test]
+    fn breakpoints_work() {
+        let mut debugger = debugger();
+        let id = debugger.add(Point::Break(4)).unwrap();
+
+        assert!(matches!(
+            resume(&mut debugger, Resume::Continue),
+            Stop::Breakpoint { id: hit } if hit == id
+        ));
+        assert_eq!(debugger.interpreter().program_counter(), 4);
+        assert!(matches!(
+            resume(&mut debugger, Resume::Continue),
+            Stop::Breakpoint { .. }
+        ));
+        assert_eq!(debugger.interpreter().memory()[1], 1);
+
+        debugger.remove(id).unwrap();
+        assert!(matches!(
+            resume(&mut debugger, Resume::Continue),
+            Stop::Halted
+        ));
+    }
+
+    #[test]
+    fn watchpoints_work() {
+        let mut debugger = debugger();
+        debugger.add(Point::Watch(Watch::Cell(1))).unwrap();
+
+        assert!(matches!(
+            resume(&mut debugger, Resume::Continue),
+            Stop::Watchpoint {
+                old: Some(0),
+                new: Some(1),
+                ..
+            }
+        ));
+        assert_eq!(debugger.interpreter().program_counter(), 5);
+    }
+
+    #[test]
+    fn next_and_finish_run_loops() {
+        let mut debugger = debugger();
+        resume(&mut debugger, Resume::Step);
+        resume(&mut debugger, Resume::Step);
+        assert!(matches!(resume(&mut debugger, Resume::Next), Stop::Done));
+        assert_eq!(debugger.interpreter().program_counter(), 8);
+        assert_eq!(debugger.interpreter().memory()[1], 2);
+
+        let mut debugger = self::debugger();
+        for _ in 0..5 {
+            resume(&mut debugger, Resume::Step);
+        }
+        assert!(matches!(resume(&mut debugger, Resume::Finish), Stop::Done));
+        assert_eq!(debugger.interpreter().program_counter(), 8);
+        assert!(debugger
+            .resume(Resume::Finish, &mut &b""[..], &mut vec![])
+            .is_err());
+    }
+
+    #[test]
+    fn resume_for_goes_on() {
+        let mut debugger = debugger();
+        resume(&mut debugger, Resume::Step);
+        resume(&mut debugger, Resume::Step);
+        let mut slices = 1;
+        let stop = loop {
+            match debugger.resume_for(Resume::Next, 3, &mut &b""[..], &mut vec![]) {
+                Ok(Some(stop)) => break stop,
+                Ok(None) => slices += 1,
+                Err(e) => panic!("{e}"),
+            }
+        };
+
+        assert!(matches!(stop, Stop::Done));
+        assert_eq!(slices, 4);
+        assert_eq!(debugger.interpreter().program_counter(), 8);
+        assert_eq!(debugger.interpreter().memory()[1], 2);
+    }
+
+    #[test]
+    fn eval_works() {
+        let mut debugger = debugger();
+        let mut output = vec![];
+        for line in [
+            "break 5",
+            "continue",
+            "",
+            "print 0 1",
+            "delete 1",
+            "step 100",
+        ] {
+            debugger.eval(line, &mut &b""[..], &mut output).unwrap();
+        }
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "Breakpoint 1 at #4 (5:1) MoO\n\
+             Breakpoint 1\n#4 (5:1) MoO\n0..=6: 2 [0] 0 0 0 0 0 | pointer 1 | register -\n\
+             Breakpoint 1\n#4 (5:1) MoO\n0..=6: 1 [1] 0 0 0 0 0 | pointer 1 | register -\n\
+             0: 1 [1]\n\
+             0\nThe program ended\n"
+        );
+    }
+
+    #[test]
+    fn reverse_works() {
+        let mut debugger = debugger();
+        debugger.add(Point::Break(4)).unwrap();
+        assert!(matches!(
+            resume(&mut debugger, Resume::Continue),
+            Stop::Breakpoint { .. }
+        ));
+        assert!(matches!(
+            resume(&mut debugger, Resume::Continue),
+            Stop::Breakpoint { .. }
+        ));
+        assert_eq!(debugger.interpreter().memory()[..2], [1, 1]);
+
+        assert!(matches!(
+            resume(&mut debugger, Resume::ReverseStep),
+            Stop::Done
+        ));
+        assert_eq!(debugger.interpreter().program_counter(), 3);
+        assert!(matches!(
+            resume(&mut debugger, Resume::ReverseContinue),
+            Stop::Breakpoint { .. }
+        ));
+        assert_eq!(debugger.interpreter().program_counter(), 4);
+        assert_eq!(debugger.interpreter().memory()[..2], [2, 0]);
+
+        debugger.add(Point::Watch(Watch::Cell(0))).unwrap();
+        assert!(matches!(
+            resume(&mut debugger, Resume::ReverseContinue),
+            Stop::Watchpoint {
+                old: Some(1),
+                new: Some(2),
+                ..
+            }
+        ));
+        assert_eq!(debugger.interpreter().program_counter(), 1);
+        assert!(matches!(
+            resume(&mut debugger, Resume::ReverseContinue),
+            Stop::Watchpoint { .. }
+        ));
+        assert!(matches!(
+            resume(&mut debugger, Resume::ReverseContinue),
+            Stop::Beginning
+        ));
+    }
+}
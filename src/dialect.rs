@@ -0,0 +1,317 @@
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+
+use crate::{
+    errors::TranslateError,
+    instruction::{AsInstruction, Instruction},
+    translate,
+};
+
+/// What the tokens of a dialect stand for.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Semantics {
+    /// Each token is a COW instruction.
+    #[default]
+    Cow,
+    /// Each token is a Brainfuck command, which is translated into COW after lexing.
+    Brainfuck,
+}
+
+/// A command a token stands for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    Cow(Instruction),
+    Brainfuck(u8),
+}
+
+impl Command {
+    /// Returns `true` for `MOO` and `[`.
+    pub fn is_begin_loop(self) -> bool {
+        matches!(
+            self,
+            Self::Cow(Instruction::BeginLoop) | Self::Brainfuck(b'[')
+        )
+    }
+
+    /// Returns `true` for `moo` and `]`.
+    pub fn is_end_loop(self) -> bool {
+        matches!(
+            self,
+            Self::Cow(Instruction::EndLoop) | Self::Brainfuck(b']')
+        )
+    }
+}
+
+/// A token table of a tape language which lowers to COW [`Instruction`]s.
+///
+/// A space in a token matches one or more whitespace characters of the source, so that e.g.
+/// `Ook. Ook?` also matches across a line break.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dialect {
+    name: String,
+    semantics: Semantics,
+    /// Token and command pairs, longest token first.
+    tokens: Vec<(Vec<u8>, Command)>,
+}
+
+/// The TOML representation of a user-defined dialect.
+///
+/// ```toml
+/// name = "moo-es"
+/// semantics = "cow" # or "brainfuck"
+///
+/// [tokens]
+/// muu = "moo" # a COW instruction, or a Brainfuck command such as "+"
+/// ```
+#[derive(Deserialize)]
+struct DialectFile {
+    name: String,
+    #[serde(default)]
+    semantics: Semantics,
+    tokens: BTreeMap<String, String>,
+}
+
+const BRAINFUCK_COMMANDS: &[u8] = b"+-<>,.[]";
+
+impl Dialect {
+    pub fn new(
+        name: impl Into<String>,
+        semantics: Semantics,
+        tokens: Vec<(Vec<u8>, Command)>,
+    ) -> Result<Self> {
+        let name = name.into();
+        for (token, command) in &tokens {
+            let expected = match command {
+                Command::Cow(_) => Semantics::Cow,
+                Command::Brainfuck(_) => Semantics::Brainfuck,
+            };
+            if token.is_empty() || token.iter().all(u8::is_ascii_whitespace) {
+                bail!("Dialect `{name}` has an empty token");
+            }
+            if expected != semantics {
+                bail!(
+                    "Token `{}` of dialect `{name}` doesn't match its semantics ({semantics:?})",
+                    String::from_utf8_lossy(token)
+                );
+            }
+        }
+        let mut tokens = tokens;
+        tokens.sort_by_key(|(token, _)| std::cmp::Reverse(token.len()));
+        Ok(Self {
+            name,
+            semantics,
+            tokens,
+        })
+    }
+
+    /// COW itself.
+    pub fn cow() -> Self {
+        let tokens = (0..12)
+            .filter_map(|code: i32| code.as_instruction())
+            .map(|instruction| (instruction.as_str().into(), Command::Cow(instruction)))
+            .collect();
+        Self::new("cow", Semantics::Cow, tokens).unwrap()
+    }
+
+    pub fn brainfuck() -> Self {
+        let tokens = BRAINFUCK_COMMANDS
+            .iter()
+            .map(|&command| (vec![command], Command::Brainfuck(command)))
+            .collect();
+        Self::new("brainfuck", Semantics::Brainfuck, tokens).unwrap()
+    }
+
+    /// [Ook!](https://esolangs.org/wiki/Ook!)
+    pub fn ook() -> Self {
+        let tokens = [
+            ("Ook. Ook?", b'>'),
+            ("Ook? Ook.", b'<'),
+            ("Ook. Ook.", b'+'),
+            ("Ook! Ook!", b'-'),
+            ("Ook! Ook.", b'.'),
+            ("Ook. Ook!", b','),
+            ("Ook! Ook?", b'['),
+            ("Ook? Ook!", b']'),
+        ]
+        .into_iter()
+        .map(|(token, command)| (token.into(), Command::Brainfuck(command)))
+        .collect();
+        Self::new("ook", Semantics::Brainfuck, tokens).unwrap()
+    }
+
+    /// Returns the built-in dialect called `name`.
+    pub fn builtin(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "cow" => Some(Self::cow()),
+            "brainfuck" | "bf" => Some(Self::brainfuck()),
+            "ook" => Some(Self::ook()),
+            _ => None,
+        }
+    }
+
+    /// Guesses the dialect of a source from the extension of its path, e.g. `.b` or `.ook`,
+    /// falling back to COW.
+    pub fn guess(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("b") => Self::brainfuck(),
+            Some(extension) => Self::builtin(extension).unwrap_or_default(),
+            None => Self::cow(),
+        }
+    }
+
+    /// Loads a user-defined dialect from a TOML file.
+    pub fn load(path: &Path) -> Result<Self> {
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read `{}`", path.display()))?;
+        Self::from_toml(&source).with_context(|| format!("Invalid dialect `{}`", path.display()))
+    }
+
+    pub fn from_toml(source: &str) -> Result<Self> {
+        let file: DialectFile = toml::from_str(source)?;
+        let tokens = file
+            .tokens
+            .into_iter()
+            .map(|(token, name)| {
+                let command = match file.semantics {
+                    Semantics::Cow => <[u8; 3]>::try_from(name.as_bytes())
+                        .ok()
+                        .and_then(|bytes| bytes.as_instruction())
+                        .map(Command::Cow),
+                    Semantics::Brainfuck => match name.as_bytes() {
+                        [byte] if BRAINFUCK_COMMANDS.contains(byte) => {
+                            Some(Command::Brainfuck(*byte))
+                        }
+                        _ => None,
+                    },
+                };
+                match command {
+                    Some(command) => Ok((token.into_bytes(), command)),
+                    None => bail!("Unknown command `{name}` for token `{token}`"),
+                }
+            })
+            .collect::<Result<_>>()?;
+        Self::new(file.name, file.semantics, tokens)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn semantics(&self) -> Semantics {
+        self.semantics
+    }
+
+    /// Returns the command of the token at the beginning of `bytes` and the number of bytes the
+    /// token occupies.
+    pub fn match_at(&self, bytes: &[u8]) -> Option<(usize, Command)> {
+        self.tokens
+            .iter()
+            .find_map(|(token, command)| match_token(token, bytes).map(|length| (length, *command)))
+    }
+
+    /// Lowers the commands lexed with this dialect into COW instructions.
+    pub fn lower(&self, commands: Vec<Command>) -> Result<Vec<Instruction>, TranslateError> {
+        match self.semantics {
+            Semantics::Cow => Ok(commands
+                .into_iter()
+                .filter_map(|command| match command {
+                    Command::Cow(instruction) => Some(instruction),
+                    Command::Brainfuck(_) => None,
+                })
+                .collect()),
+            Semantics::Brainfuck => {
+                let source: Vec<u8> = commands
+                    .into_iter()
+                    .filter_map(|command| match command {
+                        Command::Brainfuck(byte) => Some(byte),
+                        Command::Cow(_) => None,
+                    })
+                    .collect();
+                translate::brainfuck_to_cow(&source)
+            }
+        }
+    }
+}
+
+impl Default for Dialect {
+    fn default() -> Self {
+        Self::cow()
+    }
+}
+
+fn match_token(token: &[u8], bytes: &[u8]) -> Option<usize> {
+    let mut position = 0;
+    for &expected in token {
+        if expected == b' ' {
+            let whitespace = bytes[position..]
+                .iter()
+                .take_while(|byte| byte.is_ascii_whitespace())
+                .count();
+            if whitespace == 0 {
+                return None;
+            }
+            position += whitespace;
+        } else if bytes.get(position) == Some(&expected) {
+            position += 1;
+        } else {
+            return None;
+        }
+    }
+    Some(position)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruction::Instruction::*;
+
+    #[test]
+    fn match_at_works() {
+        let ook = Dialect::ook();
+
+        assert_eq!(
+            ook.match_at(b"Ook.\n  Ook? Ook."),
+            Some((11, Command::Brainfuck(b'>')))
+        );
+        assert_eq!(ook.match_at(b"Ook.Ook?"), None);
+        // Glued tokens are matched one by one, and only the lexer rejects them in strict mode.
+        assert_eq!(
+            Dialect::cow().match_at(b"MoOMoO"),
+            Some((3, Command::Cow(IncrementByte)))
+        );
+    }
+
+    #[test]
+    fn from_toml_works() {
+        let dialect = Dialect::from_toml(
+            r#"
+            name = "moo-es"
+
+            [tokens]
+            muu = "moo"
+            MUU = "MOO"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(dialect.name(), "moo-es");
+        assert_eq!(dialect.semantics(), Semantics::Cow);
+        assert_eq!(dialect.match_at(b"MUU"), Some((3, Command::Cow(BeginLoop))));
+    }
+
+    #[test]
+    fn from_toml_rejects_unknown_commands() {
+        let source = r#"
+            name = "broken"
+            semantics = "brainfuck"
+
+            [tokens]
+            plus = "MoO"
+        "#;
+
+        assert!(Dialect::from_toml(source).is_err());
+    }
+}
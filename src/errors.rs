@@ -1,10 +1,14 @@
+use crate::instruction::Instruction;
+
 #[derive(Debug, Copy, Clone)]
 pub enum ErrorKind {
     InfiniteLoop,
+    Interrupted,
     InvalidCode,
     NotAscii,
     NotInteger,
     OverFlow,
+    RepeatedState,
     UnmatchedBeginLoop,
     UnmatchedEndLoop,
 }
@@ -15,10 +19,14 @@ impl ErrorKind {
             Self::InfiniteLoop => {
                 Some("Code 3 (`mOO`) can't execute itself as it would cause an infinite loop")
             }
+            Self::Interrupted => Some("The program was interrupted"),
             Self::InvalidCode => Some("Code values must be between 0 and 11"),
             Self::NotAscii => Some("Expect ASCII charactors but given invalid value"),
             Self::NotInteger => Some("Expect 32-bit signed integer but given invalid value"),
             Self::OverFlow => Some("Current memory value has overflowed"),
+            Self::RepeatedState => {
+                Some("The program came back to the same state, so it would loop forever")
+            }
             Self::UnmatchedBeginLoop => Some("Could not find matching `MOO` command"),
             Self::UnmatchedEndLoop => Some("Could not find matching `moo` command"),
         }
@@ -30,3 +38,57 @@ impl std::fmt::Display for ErrorKind {
         self.as_str().unwrap_or_default().fmt(f)
     }
 }
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TranslateError {
+    /// Instructions that have no equivalent in the target language, with their indexes.
+    Unsupported(Vec<(usize, Instruction)>),
+    /// A loop whose matching command can't be found (or isn't well nested) in the source.
+    UnmatchedLoop(usize),
+}
+
+impl std::fmt::Display for TranslateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unsupported(instructions) => {
+                write!(f, "Unsupported instructions:")?;
+                for (index, instruction) in instructions {
+                    write!(f, "\n\t`{instruction}` at instruction {index}")?;
+                }
+                Ok(())
+            }
+            Self::UnmatchedLoop(index) => {
+                write!(f, "Loop at {index} has no well-nested matching command")
+            }
+        }
+    }
+}
+
+impl std::error::Error for TranslateError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LexError {
+    /// The source isn't valid UTF-8, e.g. a binary file or a UTF-16 text.
+    InvalidUtf8 { line: usize, column: usize },
+    /// The lexed commands couldn't be lowered into COW instructions.
+    Lower(TranslateError),
+}
+
+impl std::fmt::Display for LexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidUtf8 { line, column } => {
+                write!(f, "Invalid UTF-8 sequence at {line}:{column}")
+            }
+            Self::Lower(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for LexError {}
+
+impl From<TranslateError> for LexError {
+    fn from(e: TranslateError) -> Self {
+        Self::Lower(e)
+    }
+}
@@ -0,0 +1,281 @@
+use anyhow::{bail, Context, Result};
+
+use crate::{dialect::Command, lexer::Lexer};
+
+/// How [`format`] lays out a program.
+#[derive(Debug, Clone)]
+pub struct Style {
+    /// Maximum number of tokens on a line.
+    pub tokens_per_line: usize,
+    /// Number of spaces per `MOO`…`moo` nesting level.
+    pub indent: usize,
+    /// Puts runs of at least this many identical tokens on their own lines, after a comment
+    /// such as `[[ MoO ×13 ]]`. Needs comment delimiters.
+    pub fold: Option<usize>,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Self {
+            tokens_per_line: 10,
+            indent: 4,
+            fold: None,
+        }
+    }
+}
+
+/// A piece of the source, as far as the formatter is concerned.
+#[derive(Debug, PartialEq)]
+enum Item<'a> {
+    Token {
+        text: String,
+        command: Command,
+    },
+    /// A line of text which isn't a token.
+    Comment {
+        text: &'a str,
+        trailing: bool,
+    },
+    /// One or more empty lines.
+    Blank,
+}
+
+/// Re-emits the source of `lexer` with `style`.
+///
+/// Tokens are separated by single spaces and indented per loop nesting level. Everything else is
+/// kept as comments, one per line, except text following a token on the same line, which stays
+/// there. Runs of empty lines are collapsed into one. The byte order mark, if any, is kept.
+pub fn format(lexer: &Lexer, style: &Style) -> Result<String> {
+    let source = std::str::from_utf8(lexer.source()).context("The source isn't valid UTF-8")?;
+    let delimiters = match (style.fold, lexer.comment_delimiters()) {
+        (None, _) => None,
+        (Some(_), Some((start, end))) => Some((
+            String::from_utf8_lossy(start).into_owned(),
+            String::from_utf8_lossy(end).into_owned(),
+        )),
+        (Some(_), None) => bail!("Folding runs needs comment delimiters"),
+    };
+
+    let mut items = vec![];
+    let mut position = 0;
+    for token in lexer.tokens() {
+        push_gap(&mut items, &source[position..token.offset], &delimiters);
+        let text = &source[token.offset..][..token.length];
+        items.push(Item::Token {
+            text: text.split_ascii_whitespace().collect::<Vec<_>>().join(" "),
+            command: token.command,
+        });
+        position = token.offset + token.length;
+    }
+    push_gap(&mut items, &source[position..], &delimiters);
+    if items.last() == Some(&Item::Blank) {
+        items.pop();
+    }
+
+    let bom = String::from_utf8_lossy(lexer.bom().unwrap_or_default());
+    let output = bom.into_owned() + &render(&items, style, &delimiters);
+
+    let before: Vec<_> = lexer
+        .tokens()
+        .into_iter()
+        .map(|token| token.command)
+        .collect();
+    let after: Vec<_> = lexer
+        .with_source(output.clone().into_bytes())
+        .tokens()
+        .into_iter()
+        .map(|token| token.command)
+        .collect();
+    if before != after {
+        bail!("Formatting would change the program, most likely because of a comment");
+    }
+
+    Ok(output)
+}
+
+/// Splits the text between two tokens into comments and blank lines.
+fn push_gap<'a>(items: &mut Vec<Item<'a>>, gap: &'a str, delimiters: &Option<(String, String)>) {
+    let lines: Vec<_> = gap.split('\n').collect();
+    for (index, line) in lines.iter().enumerate() {
+        let text = line.trim();
+        if text.is_empty() {
+            // Only a line which is empty from start to end counts.
+            let blank = index != 0 && index != lines.len() - 1;
+            if blank && !matches!(items.last(), None | Some(Item::Blank)) {
+                items.push(Item::Blank);
+            }
+            continue;
+        }
+        if let Some((start, end)) = delimiters {
+            if is_fold_comment(text, start, end) {
+                continue;
+            }
+        }
+        items.push(Item::Comment {
+            text,
+            trailing: index == 0 && matches!(items.last(), Some(Item::Token { .. })),
+        });
+    }
+}
+
+/// Returns `true` for comments made by folding, which are made again each time.
+fn is_fold_comment(text: &str, start: &str, end: &str) -> bool {
+    text.strip_prefix(start)
+        .and_then(|text| text.strip_suffix(end))
+        .and_then(|text| text.trim().rsplit_once(" ×"))
+        .is_some_and(|(token, count)| !token.is_empty() && count.parse::<usize>().is_ok())
+}
+
+fn render(items: &[Item], style: &Style, delimiters: &Option<(String, String)>) -> String {
+    let tokens_per_line = style.tokens_per_line.max(1);
+    let mut output = String::new();
+    let mut line: Vec<&str> = vec![];
+    let mut depth: usize = 0;
+    // Depth of the line being built, which isn't `depth` anymore right after `MOO`.
+    let mut line_depth = 0;
+    // The line is finished but may still get a trailing comment.
+    let mut closed = false;
+
+    let flush = |output: &mut String, line: &mut Vec<&str>, line_depth: usize| {
+        if !line.is_empty() {
+            output.push_str(&" ".repeat(line_depth * style.indent));
+            output.push_str(&line.join(" "));
+            output.push('\n');
+            line.clear();
+        }
+    };
+
+    let mut index = 0;
+    while index < items.len() {
+        match &items[index] {
+            Item::Token { text, command } => {
+                let run = items[index..]
+                    .iter()
+                    .take_while(|item| match item {
+                        Item::Token { text: other, .. } => other == text,
+                        _ => false,
+                    })
+                    .count();
+                let folded = style.fold.filter(|&fold| {
+                    run >= fold.max(2) && !command.is_begin_loop() && !command.is_end_loop()
+                });
+
+                if closed || command.is_end_loop() || folded.is_some() {
+                    flush(&mut output, &mut line, line_depth);
+                    closed = false;
+                }
+                if command.is_end_loop() {
+                    depth = depth.saturating_sub(1);
+                }
+                if line.is_empty() {
+                    line_depth = depth;
+                }
+
+                if let (Some(_), Some((start, end))) = (folded, delimiters) {
+                    let indent = " ".repeat(depth * style.indent);
+                    output.push_str(&format!("{indent}{start} {text} ×{run} {end}\n"));
+                    for chunk in vec![text.as_str(); run].chunks(tokens_per_line) {
+                        output.push_str(&format!("{indent}{}\n", chunk.join(" ")));
+                    }
+                    index += run;
+                    continue;
+                }
+
+                line.push(text);
+                if command.is_begin_loop() {
+                    depth += 1;
+                }
+                closed = command.is_begin_loop()
+                    || command.is_end_loop()
+                    || line.len() == tokens_per_line;
+            }
+            Item::Comment { text, trailing } => {
+                if *trailing && !line.is_empty() {
+                    line.push(text);
+                } else {
+                    flush(&mut output, &mut line, line_depth);
+                    output.push_str(&" ".repeat(depth * style.indent));
+                    output.push_str(text);
+                    output.push('\n');
+                }
+                flush(&mut output, &mut line, line_depth);
+                closed = false;
+            }
+            Item::Blank => {
+                flush(&mut output, &mut line, line_depth);
+                closed = false;
+                if !output.is_empty() {
+                    output.push('\n');
+                }
+            }
+        }
+        index += 1;
+    }
+    flush(&mut output, &mut line, line_depth);
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format_with(source: &str, style: &Style) -> String {
+        let lexer = Lexer::from_bytes(source.into()).comment("[[", "]]");
+        format(&lexer, style).unwrap()
+    }
+
+    #[test]
+    fn format_works() {
+        let source = "MoO MoO MoO MOO moO MOO MoO moo mOo MOo moo OOO";
+        let style = Style {
+            tokens_per_line: 2,
+            ..Style::default()
+        };
+
+        assert_eq!(
+            format_with(source, &style),
+            "MoO MoO\nMoO MOO\n    moO MOO\n        MoO\n    moo\n    mOo MOo\nmoo\nOOO\n"
+        );
+    }
+
+    #[test]
+    fn format_keeps_comments() {
+        let source = "[[ start ]] MoO MoO  [[ two ]]\n\n\nprose\tMoO\r\nMOO moo";
+
+        assert_eq!(
+            format_with(source, &Style::default()),
+            "[[ start ]]\nMoO MoO [[ two ]]\n\nprose\nMoO MOO\nmoo\n"
+        );
+    }
+
+    #[test]
+    fn format_folds_runs() {
+        let source = "moO MoO MoO MoO [[ MoO ×2 ]]\nMoO MoO moO";
+        let style = Style {
+            tokens_per_line: 3,
+            fold: Some(4),
+            ..Style::default()
+        };
+        let formatted = format_with(source, &style);
+
+        assert_eq!(formatted, "moO\n[[ MoO ×5 ]]\nMoO MoO MoO\nMoO MoO\nmoO\n");
+        assert_eq!(format_with(&formatted, &style), formatted);
+    }
+
+    #[test]
+    fn format_keeps_bom() {
+        let source = "\u{feff}MoO  MoO\n";
+
+        assert_eq!(format_with(source, &Style::default()), "\u{feff}MoO MoO\n");
+        assert_eq!(format_with("MoO MoO\n", &Style::default()), "MoO MoO\n");
+    }
+
+    #[test]
+    fn format_is_idempotent() {
+        let source = include_str!("../samples/hello_world.cow");
+        let formatted = format_with(source, &Style::default());
+
+        assert_eq!(format_with(&formatted, &Style::default()), formatted);
+    }
+}
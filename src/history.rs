@@ -0,0 +1,98 @@
+use std::collections::VecDeque;
+
+/// Number of steps between two checkpoints.
+pub const CHECKPOINT_INTERVAL: u64 = 10_000;
+
+/// Number of checkpoints kept. Older ones are dropped, along with the input read before them.
+pub const CHECKPOINT_LIMIT: usize = 32;
+
+/// Number of changes kept in the undo log. Steps older than that are reached by replaying from
+/// a checkpoint.
+pub const LOG_LIMIT: usize = 100_000;
+
+/// What a step changed, so that it can be undone.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Change {
+    /// Number of steps run before this one.
+    pub step: u64,
+    pub program_counter: usize,
+    pub pointer: usize,
+    pub register: Option<i32>,
+    /// The value of the current memory block before the step, if the step wrote it.
+    pub write: Option<i32>,
+}
+
+/// The state of the machine before a step.
+#[derive(Debug, Clone)]
+pub(crate) struct Checkpoint {
+    pub(crate) step: u64,
+    pub(crate) memory: Box<[i32]>,
+    pub(crate) pointer: usize,
+    pub(crate) program_counter: usize,
+    pub(crate) register: Option<i32>,
+}
+
+/// A record of the steps run by [`Interpreter::step`](crate::interpreter::Interpreter::step):
+/// an undo log of the latest ones, and checkpoints taken every [`CHECKPOINT_INTERVAL`] steps
+/// together with the input read since, to replay older ones.
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    /// Number of steps run so far.
+    pub(crate) steps: u64,
+    /// Changes of the latest steps, oldest first.
+    pub(crate) log: VecDeque<Change>,
+    /// Oldest first.
+    pub(crate) checkpoints: VecDeque<Checkpoint>,
+    /// Input read since the oldest checkpoint, with the step which read it.
+    pub(crate) inputs: VecDeque<(u64, Vec<u8>)>,
+}
+
+impl History {
+    /// Number of steps run so far.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// The oldest step which can still be gone back to.
+    pub fn oldest(&self) -> u64 {
+        let logged = self.log.front().map_or(self.steps, |change| change.step);
+        self.checkpoints
+            .front()
+            .map_or(logged, |checkpoint| checkpoint.step.min(logged))
+    }
+
+    /// Number of steps in the undo log.
+    pub fn logged(&self) -> usize {
+        self.log.len()
+    }
+
+    /// Returns the latest step in the undo log which wrote the memory block at `index`.
+    pub fn last_write(&self, index: usize) -> Option<&Change> {
+        self.log
+            .iter()
+            .rev()
+            .find(|change| change.pointer == index && change.write.is_some())
+    }
+
+    pub(crate) fn push(&mut self, change: Change, input: Vec<u8>) {
+        if !input.is_empty() {
+            self.inputs.push_back((change.step, input));
+        }
+        self.log.push_back(change);
+        if self.log.len() > LOG_LIMIT {
+            self.log.pop_front();
+        }
+        self.steps += 1;
+    }
+
+    pub(crate) fn push_checkpoint(&mut self, checkpoint: Checkpoint) {
+        self.checkpoints.push_back(checkpoint);
+        if self.checkpoints.len() > CHECKPOINT_LIMIT {
+            self.checkpoints.pop_front();
+            let oldest = self.checkpoints[0].step;
+            while self.inputs.front().is_some_and(|&(step, _)| step < oldest) {
+                self.inputs.pop_front();
+            }
+        }
+    }
+}
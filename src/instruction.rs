@@ -30,6 +30,84 @@ pub enum Instruction {
     ReadStdin,        // oom
 }
 
+impl Instruction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::EndLoop => "moo",
+            Self::DecrementPointer => "mOo",
+            Self::IncrementPointer => "moO",
+            Self::ExecuteValue => "mOO",
+            Self::ReadOrWrite => "Moo",
+            Self::DecrementByte => "MOo",
+            Self::IncrementByte => "MoO",
+            Self::BeginLoop => "MOO",
+            Self::SetZero => "OOO",
+            Self::CopyOrPaste => "MMM",
+            Self::WriteStdout => "OOM",
+            Self::ReadStdin => "oom",
+        }
+    }
+
+    /// Returns `true` for `MOO` and `moo`.
+    pub fn is_loop(self) -> bool {
+        matches!(self, Self::BeginLoop | Self::EndLoop)
+    }
+
+    /// Returns the instruction code, e.g. 7 for `MOO`.
+    pub fn code(self) -> i32 {
+        self as i32
+    }
+
+    /// Returns what the instruction does in one sentence, as Markdown. The table above has the
+    /// details.
+    pub fn summary(self) -> &'static str {
+        match self {
+            Self::EndLoop => "Jumps back to the matching `MOO`.",
+            Self::DecrementPointer => "Moves the pointer back one block.",
+            Self::IncrementPointer => "Moves the pointer forward one block.",
+            Self::ExecuteValue => "Runs the instruction whose code is in the current block.",
+            Self::ReadOrWrite => {
+                "Reads an ASCII character into the current block if it's 0, or prints it."
+            }
+            Self::DecrementByte => "Decrements the current block.",
+            Self::IncrementByte => "Increments the current block.",
+            Self::BeginLoop => "Jumps past the matching `moo` if the current block is 0.",
+            Self::SetZero => "Sets the current block to 0.",
+            Self::CopyOrPaste => "Copies the current block to the register, or pastes it back.",
+            Self::WriteStdout => "Prints the current block as an integer.",
+            Self::ReadStdin => "Reads an integer into the current block.",
+        }
+    }
+}
+
+impl std::fmt::Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
+impl std::str::FromStr for Instruction {
+    type Err = anyhow::Error;
+
+    /// Parses the name of an instruction, e.g. `MOO`.
+    fn from_str(name: &str) -> anyhow::Result<Self> {
+        (0..12)
+            .filter_map(|code: i32| code.as_instruction())
+            .find(|instruction| instruction.as_str() == name)
+            .ok_or_else(|| anyhow::anyhow!("`{name}` isn't an instruction"))
+    }
+}
+
+/// FNV-1a hash of the instruction codes of `program`, which stays the same across builds and
+/// platforms.
+pub fn hash(program: &[Instruction]) -> u64 {
+    program
+        .iter()
+        .fold(0xcbf29ce484222325, |hash, instruction| {
+            (hash ^ instruction.code() as u64).wrapping_mul(0x100000001b3)
+        })
+}
+
 pub trait AsInstruction {
     fn as_instruction(&self) -> Option<Instruction>;
 }
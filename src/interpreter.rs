@@ -1,20 +1,64 @@
-use std::io::{self, BufRead, Read, Write};
+use std::{
+    collections::VecDeque,
+    io::{self, BufRead, Read, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
 
-use anyhow::{bail, ensure, Result};
+use anyhow::{bail, ensure, Context, Result};
 
 use crate::{
     errors::ErrorKind,
-    instruction::{AsInstruction, Instruction},
+    history::{Change, Checkpoint, History, CHECKPOINT_INTERVAL},
+    instruction::{self, AsInstruction, Instruction},
+    observer::{Io, Observer},
+    snapshot::{Snapshot, SourceOptions},
 };
 
-const MEMORY_SIZE: usize = 30000;
+pub const MEMORY_SIZE: usize = 30000;
+
+/// What happened in a call of [`Interpreter::step`].
+#[derive(Debug)]
+pub enum Event {
+    /// An instruction ran without anything else to report.
+    Stepped,
+    /// An instruction wrote these bytes: one for `Moo`, the decimal digits for `OOM`.
+    Output(Vec<u8>),
+    /// The next instruction reads input, but not enough was given with
+    /// [`Interpreter::push_input`]. Nothing ran.
+    NeedInput,
+    /// `MOO` or `moo` jumped, so that the next instruction is at `to` rather than `from + 1`.
+    Jumped { from: usize, to: usize },
+    /// The program counter is past the end of the program. Nothing ran.
+    Halted,
+    /// The instruction failed. The program counter stays on it.
+    Error(anyhow::Error),
+}
 
+#[derive(Clone)]
 pub struct Interpreter {
     program: Vec<Instruction>,
     memory: [i32; MEMORY_SIZE],
     pointer: usize,
     program_counter: usize,
     register: Option<i32>,
+    /// `Some` if repeated states should be detected.
+    repeats: Option<Repeats>,
+    /// Input given with [`Interpreter::push_input`] and not read yet.
+    input: VecDeque<u8>,
+    /// Number of bytes of input read so far.
+    input_position: u64,
+    /// `Some` if the steps should be recorded.
+    history: Option<History>,
+    /// The value of the current memory block before the running step wrote it, if it did.
+    written: Option<i32>,
+    /// Tools seeing every step, shared with the caller that reads what they saw.
+    observers: Vec<Arc<Mutex<dyn Observer + Send>>>,
+    /// `Some` if the run should stop once it's set.
+    interrupt: Option<Arc<AtomicBool>>,
 }
 
 impl Interpreter {
@@ -25,7 +69,351 @@ impl Interpreter {
             pointer: 0,
             program_counter: 0,
             register: None,
+            repeats: None,
+            input: VecDeque::new(),
+            input_position: 0,
+            history: None,
+            written: None,
+            observers: vec![],
+            interrupt: None,
+        }
+    }
+
+    /// Stops the program with [`ErrorKind::RepeatedState`] when it jumps back to a loop in the
+    /// exact state it was in before, which means it would loop forever. Costs a hash update per
+    /// memory write and a check per jump back.
+    pub fn detect_repeats(mut self, detect: bool) -> Self {
+        self.repeats = detect.then(|| Repeats::new(&self.memory));
+        self
+    }
+
+    /// Records the steps run by [`Interpreter::step`], so that they can be undone with
+    /// [`Interpreter::step_back`] and [`Interpreter::travel`]. The memory used is bounded: only
+    /// the latest steps are kept in an undo log, and older ones are replayed from checkpoints.
+    pub fn record(mut self, record: bool) -> Self {
+        self.history = record.then(History::default);
+        self
+    }
+
+    /// Stops the program with [`ErrorKind::Interrupted`] once `flag` is set, e.g. by a signal
+    /// handler. The flag is only read when `moo` jumps back and before input is read, which is
+    /// where a program that doesn't end spends its time. The program counter stays on that
+    /// instruction.
+    pub fn interrupt_on(mut self, flag: Arc<AtomicBool>) -> Self {
+        self.interrupt = Some(flag);
+        self
+    }
+
+    /// Reports the steps to `observer`, after the observers added before, e.g. a
+    /// [`Profiler`](crate::profile::Profiler) or a [`Coverage`](crate::coverage::Coverage). The
+    /// caller keeps a clone of `observer` to read what it saw. Without observers,
+    /// [`Interpreter::run_with`] costs nothing more than the instructions.
+    pub fn observe<O: Observer + Send + 'static>(mut self, observer: Arc<Mutex<O>>) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    /// Replaces the program with `program`, keeping the memory, the pointer and the register,
+    /// so that the next run continues from the current state. Observers are kept too, so only
+    /// those which don't depend on the program should be added when programs are replaced.
+    pub fn load(&mut self, program: Vec<Instruction>) {
+        self.program = program;
+        self.program_counter = 0;
+        self.forget_states();
+        if let Some(history) = &mut self.history {
+            *history = History::default();
+        }
+    }
+
+    /// The steps recorded since [`Interpreter::record`].
+    pub fn history(&self) -> Option<&History> {
+        self.history.as_ref()
+    }
+
+    pub fn program(&self) -> &[Instruction] {
+        &self.program
+    }
+
+    pub fn memory(&self) -> &[i32] {
+        &self.memory
+    }
+
+    /// Index of the current memory block.
+    pub fn pointer(&self) -> usize {
+        self.pointer
+    }
+
+    /// Index of the next instruction to run.
+    pub fn program_counter(&self) -> usize {
+        self.program_counter
+    }
+
+    pub fn register(&self) -> Option<i32> {
+        self.register
+    }
+
+    /// Number of bytes of input read so far.
+    pub fn input_position(&self) -> u64 {
+        self.input_position
+    }
+
+    /// Returns the state of the machine, to go on later with [`Interpreter::restore`].
+    /// `source_path` is where the program is read from on resume, with the default
+    /// [`Snapshot::source_options`].
+    pub fn snapshot(&self, source_path: &Path) -> Snapshot {
+        Snapshot {
+            source_path: source_path.to_owned(),
+            source_options: SourceOptions::default(),
+            program_hash: instruction::hash(&self.program),
+            program_length: self.program.len(),
+            program_counter: self.program_counter,
+            pointer: self.pointer,
+            register: self.register,
+            input_position: self.input_position,
+            memory: self.memory.to_vec(),
+        }
+    }
+
+    /// Restores a state returned by [`Interpreter::snapshot`] for the same program. The input
+    /// read before it isn't skipped: the caller gives only what comes after
+    /// [`Snapshot::input_position`].
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<()> {
+        ensure!(
+            snapshot.program_hash == instruction::hash(&self.program)
+                && snapshot.program_length == self.program.len(),
+            "The state was saved for another program"
+        );
+        ensure!(
+            snapshot.program_counter <= self.program.len() && snapshot.pointer < MEMORY_SIZE,
+            "The state is off the program or the memory"
+        );
+        self.memory = [0; MEMORY_SIZE];
+        self.memory[..snapshot.memory.len()].copy_from_slice(&snapshot.memory);
+        self.pointer = snapshot.pointer;
+        self.program_counter = snapshot.program_counter;
+        self.register = snapshot.register;
+        self.input_position = snapshot.input_position;
+        if self.repeats.is_some() {
+            self.repeats = Some(Repeats::new(&self.memory));
+        }
+        if let Some(history) = &mut self.history {
+            *history = History::default();
+        }
+        Ok(())
+    }
+
+    /// Gives input to [`Interpreter::step`]. `Moo` reads a single byte, and `oom` reads a line
+    /// ending with `\n`.
+    pub fn push_input(&mut self, input: &[u8]) {
+        self.input.extend(input);
+    }
+
+    /// Runs the next instruction.
+    ///
+    /// Input comes from [`Interpreter::push_input`] rather than STDIN, and output is returned
+    /// rather than written to STDOUT, so that the caller decides where they go.
+    pub fn step(&mut self) -> Event {
+        let Some(&instruction) = self.program.get(self.program_counter) else {
+            return Event::Halted;
+        };
+        if self.needs_input(instruction) {
+            return Event::NeedInput;
+        }
+
+        let from = self.program_counter;
+        let change = self.history.as_mut().map(|history| {
+            if history.steps % CHECKPOINT_INTERVAL == 0 {
+                history.push_checkpoint(Checkpoint {
+                    step: history.steps,
+                    memory: self.memory.into(),
+                    pointer: self.pointer,
+                    program_counter: self.program_counter,
+                    register: self.register,
+                });
+            }
+            // At most a line is read by a step.
+            let line = self
+                .input
+                .iter()
+                .position(|&byte| byte == b'\n')
+                .map_or(self.input.len(), |index| index + 1);
+            let change = Change {
+                step: history.steps,
+                program_counter: from,
+                pointer: self.pointer,
+                register: self.register,
+                write: None,
+            };
+            (
+                change,
+                self.input.range(..line).copied().collect::<Vec<_>>(),
+            )
+        });
+        let available = self.input.len();
+        self.written = None;
+        self.notify(|observer, interpreter| observer.before_step(interpreter));
+
+        let mut input = std::mem::take(&mut self.input);
+        let mut output = vec![];
+        let result = self.instruction_matches(instruction, &mut input, &mut output);
+        self.input = input;
+        if let Err(e) = result {
+            self.notify(|observer, interpreter| observer.on_error(interpreter, &e));
+            // The time between steps isn't the program's.
+            self.notify(|observer, interpreter| observer.on_stop(interpreter));
+            return Event::Error(e);
+        }
+        self.program_counter += 1;
+        self.notify_step(from);
+        self.notify(|observer, interpreter| observer.on_stop(interpreter));
+        if let (Some(history), Some((change, mut line))) = (&mut self.history, change) {
+            line.truncate(available - self.input.len());
+            let change = Change {
+                write: self.written,
+                ..change
+            };
+            history.push(change, line);
+        }
+
+        if !output.is_empty() {
+            Event::Output(output)
+        } else if self.program_counter != from + 1 {
+            Event::Jumped {
+                from,
+                to: self.program_counter,
+            }
+        } else {
+            Event::Stepped
+        }
+    }
+
+    /// Undoes the last step recorded. Returns `false` if there is no step to undo.
+    pub fn step_back(&mut self) -> Result<bool> {
+        let history = self.history.as_ref().context("The steps aren't recorded")?;
+        if history.steps == 0 {
+            return Ok(false);
+        }
+        self.travel(history.steps - 1)?;
+        Ok(true)
+    }
+
+    /// Goes back to the state right before `step`, as counted by [`History::steps`].
+    ///
+    /// Steps in the undo log are undone one by one. Older ones are reached by restoring the
+    /// latest checkpoint before `step` and running the program again from there, reading the
+    /// same input. Its output isn't written again.
+    pub fn travel(&mut self, step: u64) -> Result<()> {
+        let history = self.history.as_mut().context("The steps aren't recorded")?;
+        ensure!(step <= history.steps, "Step {step} hasn't run yet");
+        ensure!(
+            history.oldest() <= step,
+            "Step {step} is older than the oldest checkpoint"
+        );
+
+        while history.steps > step {
+            let Some(change) = history.log.pop_back() else {
+                break;
+            };
+            history.steps -= 1;
+            if history
+                .inputs
+                .back()
+                .is_some_and(|&(other, _)| other == change.step)
+            {
+                let (_, line) = history.inputs.pop_back().unwrap();
+                self.input_position -= line.len() as u64;
+                for byte in line.into_iter().rev() {
+                    self.input.push_front(byte);
+                }
+            }
+            self.program_counter = change.program_counter;
+            self.pointer = change.pointer;
+            self.register = change.register;
+            if let Some(old) = change.write {
+                let new = std::mem::replace(&mut self.memory[change.pointer], old);
+                if let Some(repeats) = &mut self.repeats {
+                    repeats.update(change.pointer, new, old);
+                }
+            }
+            history.steps = change.step;
+        }
+
+        if history.steps > step {
+            let index = history
+                .checkpoints
+                .iter()
+                .rposition(|checkpoint| checkpoint.step <= step)
+                .context("No checkpoint to replay from")?;
+            // Taken again while replaying.
+            let checkpoint = history.checkpoints.drain(index..).next().unwrap();
+            let first = history
+                .inputs
+                .iter()
+                .position(|&(other, _)| other >= checkpoint.step)
+                .unwrap_or(history.inputs.len());
+            let mut input: VecDeque<_> = history
+                .inputs
+                .drain(first..)
+                .flat_map(|(_, line)| line)
+                .collect();
+            self.input_position -= input.len() as u64;
+            input.extend(self.input.drain(..));
+            self.input = input;
+            history.log.clear();
+            history.steps = checkpoint.step;
+
+            self.memory.copy_from_slice(&checkpoint.memory);
+            self.pointer = checkpoint.pointer;
+            self.program_counter = checkpoint.program_counter;
+            self.register = checkpoint.register;
+            if self.repeats.is_some() {
+                self.repeats = Some(Repeats::new(&self.memory));
+            }
+
+            log::debug!("Replaying from step {} to {step}", checkpoint.step);
+            while self
+                .history
+                .as_ref()
+                .is_some_and(|history| history.steps < step)
+            {
+                match self.step() {
+                    Event::Error(e) => return Err(e.context("Failed to replay the program")),
+                    Event::NeedInput | Event::Halted => bail!("Failed to replay the program"),
+                    _ => {}
+                }
+            }
+        }
+        self.forget_states();
+        Ok(())
+    }
+
+    /// Returns `true` if `instruction` would read more input than was given.
+    fn needs_input(&self, instruction: Instruction) -> bool {
+        let value = self.memory[self.pointer];
+        let instruction = match instruction {
+            Instruction::ExecuteValue => value.as_instruction(),
+            _ => Some(instruction),
+        };
+        match instruction {
+            Some(Instruction::ReadOrWrite) => value == 0 && self.input.is_empty(),
+            Some(Instruction::ReadStdin) => !self.input.contains(&b'\n'),
+            _ => false,
+        }
+    }
+
+    /// Writes `value` into the current memory block.
+    fn write(&mut self, value: i32) {
+        let old = self.set_block(self.pointer, value);
+        self.written.get_or_insert(old);
+    }
+
+    /// Writes `value` into the memory block at `index`, returning the old value.
+    fn set_block(&mut self, index: usize, value: i32) -> i32 {
+        let old = std::mem::replace(&mut self.memory[index], value);
+        if let Some(repeats) = &mut self.repeats {
+            repeats.update(index, old, value);
         }
+        old
     }
 
     fn instruction_matches<R, W>(
@@ -55,31 +443,106 @@ impl Interpreter {
     }
 
     pub fn run(mut self) -> Result<Self> {
-        let mut stdin = io::stdin().lock();
-        let mut stdout = io::stdout().lock();
+        self.run_with(&mut io::stdin().lock(), &mut io::stdout().lock())?;
+        Ok(self)
+    }
 
-        loop {
-            if self.program_counter >= self.program.len() {
-                log::debug!("Completed successfully.");
-                break Ok(self);
-            }
+    /// Runs the program until it ends, reading from `stdin` and writing to `stdout`.
+    pub fn run_with<R, W>(&mut self, stdin: &mut R, stdout: &mut W) -> Result<()>
+    where
+        R: BufRead + Read,
+        W: Write,
+    {
+        let result = if self.observers.is_empty() {
+            self.run_plain(stdin, stdout)
+        } else {
+            self.run_observed(stdin, stdout)
+        };
+        if result.is_ok() {
+            log::debug!("Completed successfully.");
+        }
+        result
+    }
 
-            self.instruction_matches(self.program[self.program_counter], &mut stdin, &mut stdout)?;
+    /// Runs the program without observers, as fast as it can.
+    fn run_plain<R, W>(&mut self, stdin: &mut R, stdout: &mut W) -> Result<()>
+    where
+        R: BufRead + Read,
+        W: Write,
+    {
+        while let Some(&instruction) = self.program.get(self.program_counter) {
+            self.instruction_matches(instruction, stdin, stdout)?;
+            self.log_state();
+            self.program_counter += 1;
+        }
+        Ok(())
+    }
 
-            log::debug!(
-                "\n\tmemory value: {:?}\n\tpointer: {}\n\tregister: {:?}\n\tmemory state: {:?}",
-                self.memory[self.pointer],
-                self.pointer,
-                self.register,
-                &self.memory[..20]
-            );
+    /// Runs the program, reporting every step to the observers.
+    fn run_observed<R, W>(&mut self, stdin: &mut R, stdout: &mut W) -> Result<()>
+    where
+        R: BufRead + Read,
+        W: Write,
+    {
+        let result = loop {
+            let Some(&instruction) = self.program.get(self.program_counter) else {
+                break Ok(());
+            };
+            let from = self.program_counter;
+            self.notify(|observer, interpreter| observer.before_step(interpreter));
+            if let Err(e) = self.instruction_matches(instruction, stdin, stdout) {
+                self.notify(|observer, interpreter| observer.on_error(interpreter, &e));
+                break Err(e);
+            }
+            self.log_state();
             self.program_counter += 1;
+            self.notify_step(from);
+        };
+        self.notify(|observer, interpreter| observer.on_stop(interpreter));
+        result
+    }
+
+    fn log_state(&self) {
+        log::debug!(
+            "\n\tmemory value: {:?}\n\tpointer: {}\n\tregister: {:?}\n\tmemory state: {:?}",
+            self.memory[self.pointer],
+            self.pointer,
+            self.register,
+            &self.memory[..20]
+        );
+    }
+
+    /// Fails with [`ErrorKind::Interrupted`] if the flag given to [`Interpreter::interrupt_on`]
+    /// is set.
+    fn check_interrupt(&self) -> Result<()> {
+        if let Some(flag) = &self.interrupt {
+            ensure!(!flag.load(Ordering::Relaxed), ErrorKind::Interrupted);
+        }
+        Ok(())
+    }
+
+    /// Calls `callback` with every observer.
+    fn notify(&self, mut callback: impl FnMut(&mut dyn Observer, &Self)) {
+        for observer in &self.observers {
+            // An observer which panicked is still usable.
+            let mut observer = observer.lock().unwrap_or_else(|e| e.into_inner());
+            callback(&mut *observer, self);
+        }
+    }
+
+    /// Reports the end of the step which ran the instruction at `from`.
+    fn notify_step(&self, from: usize) {
+        if self.program_counter != from + 1 {
+            let to = self.program_counter;
+            self.notify(|observer, interpreter| observer.on_jump(interpreter, from, to));
         }
+        self.notify(|observer, interpreter| observer.after_step(interpreter, from));
     }
 
     /// moo
     fn end_loop(&mut self) -> Result<()> {
         if self.memory[self.pointer] != 0 {
+            self.check_interrupt()?;
             ensure!(2 <= self.program_counter, ErrorKind::UnmatchedBeginLoop);
             log::debug!("moo: current memory block has {} - begin executing again starting from the found `MOO` command.", self.memory[self.pointer]);
             // pc: Program counter for this loop
@@ -99,6 +562,21 @@ impl Interpreter {
                 ensure!(0 < pc, ErrorKind::UnmatchedBeginLoop);
                 pc -= 1;
             }
+            if let Some(repeats) = &mut self.repeats {
+                let state = State {
+                    program_counter: self.program_counter,
+                    pointer: self.pointer,
+                    register: self.register,
+                    hash: repeats.hash,
+                };
+                if repeats.check(state, &self.memory) {
+                    bail!(
+                        "{} (the loop at `MOO` instruction {})",
+                        ErrorKind::RepeatedState,
+                        self.program_counter
+                    );
+                }
+            }
         } else {
             log::debug!("moo: current memory block has 0 - end loop.");
         }
@@ -117,7 +595,7 @@ impl Interpreter {
 
     /// moO
     fn increment_pointer(&mut self) -> Result<()> {
-        if self.pointer == MEMORY_SIZE {
+        if self.pointer == MEMORY_SIZE - 1 {
             bail!(ErrorKind::OverFlow);
         }
         self.pointer += 1;
@@ -134,9 +612,7 @@ impl Interpreter {
         let instruction_or_none = self.memory[self.pointer].as_instruction();
         match instruction_or_none {
             None => bail!(ErrorKind::InvalidCode),
-            Some(instruction) if instruction == Instruction::ExecuteValue => {
-                bail!(ErrorKind::InfiniteLoop)
-            }
+            Some(Instruction::ExecuteValue) => bail!(ErrorKind::InfiniteLoop),
             Some(instruction) => {
                 log::debug!("mOO: execute code {}.", self.memory[self.pointer]);
                 self.instruction_matches(instruction, stdin, stdout)
@@ -146,18 +622,25 @@ impl Interpreter {
 
     /// Moo
     fn read_or_write<R: Read, W: Write>(&mut self, stdin: &mut R, stdout: &mut W) -> Result<()> {
-        let current_memory = &mut self.memory[self.pointer];
-        if *current_memory == 0 {
+        let current_memory = self.memory[self.pointer];
+        if current_memory == 0 {
             log::debug!(
                 "Moo: current memory block has 0 - read a single ASCII charactor from STDIN."
             );
+            self.check_interrupt()?;
             let mut buf = [0; 1];
             stdin.read_exact(&mut buf).unwrap();
             ensure!(buf.is_ascii(), ErrorKind::NotAscii);
-            *current_memory = buf[0] as i32;
+            self.input_position += 1;
+            self.write(buf[0] as i32);
+            self.forget_states();
+            let io = Io::ReadByte(buf[0]);
+            self.notify(|observer, interpreter| observer.on_io(interpreter, io));
         } else {
             log::debug!("Moo: current memory block has {} - write the ASCII character that corresponds to the value in the current memory block to STDOUT.", current_memory);
-            stdout.write_all(&[*current_memory as u8]).unwrap();
+            stdout.write_all(&[current_memory as u8]).unwrap();
+            let io = Io::WriteByte(current_memory as u8);
+            self.notify(|observer, interpreter| observer.on_io(interpreter, io));
         }
         Ok(())
     }
@@ -165,7 +648,7 @@ impl Interpreter {
     /// MOo
     fn decrement_byte(&mut self) -> Result<()> {
         // wrapping_add: オーバーフローを無視して減算する
-        self.memory[self.pointer] = self.memory[self.pointer].wrapping_sub(1);
+        self.write(self.memory[self.pointer].wrapping_sub(1));
         log::debug!("MOo: decrement current memory value by 1.");
         Ok(())
     }
@@ -173,7 +656,7 @@ impl Interpreter {
     /// MoO
     fn increment_byte(&mut self) -> Result<()> {
         // wrapping_sub: オーバーフローを無視して加算する
-        self.memory[self.pointer] = self.memory[self.pointer].wrapping_add(1);
+        self.write(self.memory[self.pointer].wrapping_add(1));
         log::debug!("MoO: increment current memory value by 1.");
         Ok(())
     }
@@ -215,20 +698,19 @@ impl Interpreter {
 
     /// OOO
     fn set_zero(&mut self) -> Result<()> {
-        self.memory[self.pointer] = 0;
+        self.write(0);
         log::debug!("set 0 to current memory block.");
         Ok(())
     }
 
     /// MMM
     fn copy_or_paste(&mut self) -> Result<()> {
-        let current_memory = &mut self.memory[self.pointer];
         if let Some(value) = self.register {
-            *current_memory = value;
+            self.write(value);
             self.register = None;
-            log::debug!("MMM: register has {} - paste the value into the current memory block and clear the register.", current_memory);
+            log::debug!("MMM: register has {} - paste the value into the current memory block and clear the register.", value);
         } else {
-            self.register = Some(*current_memory);
+            self.register = Some(self.memory[self.pointer]);
             log::debug!("MMM: no current value in register - copy current memory block value.");
         }
         Ok(())
@@ -236,25 +718,119 @@ impl Interpreter {
 
     /// OOM
     fn write_stdout<W: Write>(&mut self, stdout: &mut W) -> Result<()> {
-        stdout
-            .write_all(self.memory[self.pointer].to_string().as_bytes())
-            .unwrap();
+        let value = self.memory[self.pointer];
+        stdout.write_all(value.to_string().as_bytes()).unwrap();
+        let io = Io::WriteInteger(value);
+        self.notify(|observer, interpreter| observer.on_io(interpreter, io));
         log::debug!("OOM: writing value of current memory block to STDOUT as an integer.");
         Ok(())
     }
 
     /// oom
     fn read_stdin<R: Read + BufRead>(&mut self, stdin: &mut R) -> Result<()> {
+        self.check_interrupt()?;
         let mut buf = String::new();
         stdin.read_line(&mut buf).unwrap();
+        self.input_position += buf.len() as u64;
         if let Ok(integer) = buf.trim_end().parse::<i32>() {
-            self.memory[self.pointer] = integer
+            self.write(integer);
+            self.forget_states();
+            let io = Io::ReadInteger(integer);
+            self.notify(|observer, interpreter| observer.on_io(interpreter, io));
         } else {
             bail!(ErrorKind::NotInteger)
         }
         log::debug!("oom: reading an integer from STDIN and put it into the current memory block.");
         Ok(())
     }
+
+    /// Forgets the states seen so far, since the program may go on differently after reading
+    /// input even from the same state.
+    fn forget_states(&mut self) {
+        if let Some(repeats) = &mut self.repeats {
+            repeats.reset();
+        }
+    }
+}
+
+/// What identifies the state of the machine when jumping back to a loop, along with the memory.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct State {
+    program_counter: usize,
+    pointer: usize,
+    register: Option<i32>,
+    /// Hash of the memory.
+    hash: u64,
+}
+
+/// Detects repeated states with Brent's cycle detection: the state is saved whenever the number
+/// of jumps since the last save reaches a power of two, and every later state is compared with
+/// it. A cycle of any length is found once the saved state is on it and the power is past its
+/// length.
+#[derive(Clone)]
+struct Repeats {
+    /// Sum of [`block_hash`] over the memory, updated on every write.
+    hash: u64,
+    saved: Option<(State, Box<[i32]>)>,
+    power: usize,
+    jumps: usize,
+}
+
+impl Repeats {
+    fn new(memory: &[i32]) -> Self {
+        Self {
+            hash: memory
+                .iter()
+                .enumerate()
+                .fold(0, |hash: u64, (index, &value)| {
+                    hash.wrapping_add(block_hash(index, value))
+                }),
+            saved: None,
+            power: 1,
+            jumps: 0,
+        }
+    }
+
+    /// Updates the hash for the memory block at `index` going from `old` to `new`.
+    fn update(&mut self, index: usize, old: i32, new: i32) {
+        self.hash = self.hash.wrapping_sub(block_hash(index, old));
+        self.hash = self.hash.wrapping_add(block_hash(index, new));
+    }
+
+    /// Returns `true` if `state` with `memory` was seen before.
+    fn check(&mut self, state: State, memory: &[i32]) -> bool {
+        if let Some((saved, saved_memory)) = &self.saved {
+            // The hash only rules states out; the memory is compared to be sure.
+            if *saved == state && **saved_memory == *memory {
+                return true;
+            }
+        }
+        self.jumps += 1;
+        if self.saved.is_none() || self.jumps == self.power {
+            self.saved = Some((state, memory.into()));
+            self.power *= 2;
+            self.jumps = 0;
+        }
+        false
+    }
+
+    fn reset(&mut self) {
+        self.saved = None;
+        self.power = 1;
+        self.jumps = 0;
+    }
+}
+
+/// Hash of a memory block, which is 0 for blocks holding 0 so that an empty memory hashes to 0.
+fn block_hash(index: usize, value: i32) -> u64 {
+    if value == 0 {
+        return 0;
+    }
+    // SplitMix64 finalizer
+    let mut hash = (index as u64) << 32 | value as u32 as u64;
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+    hash ^ (hash >> 31)
 }
 
 #[cfg(test)]
@@ -270,6 +846,13 @@ mod tests {
                 pointer: 0,
                 program_counter: 0,
                 register: None,
+                repeats: None,
+                input: VecDeque::new(),
+                input_position: 0,
+                history: None,
+                written: None,
+                observers: vec![],
+                interrupt: None,
             }
         }
     }
@@ -361,4 +944,184 @@ mod tests {
         assert_eq!(state.memory[..5], [2, 2, 0, 0, 0]);
         assert_eq!(state.register, None);
     }
+
+    #[test]
+    fn translated_brainfuck_works() {
+        // `[[` and `]]` are padded so that `MOO` and `moo` match correctly.
+        let program = crate::translate::brainfuck_to_cow(b"++[[->+++<]]>").unwrap();
+
+        let state = run_with(program, 0);
+
+        assert_eq!(state.memory[..3], [0, 6, 0]);
+        assert_eq!(state.pointer, 1);
+    }
+
+    #[test]
+    fn increment_pointer_stops_at_the_end() {
+        // moO
+        let interpreter = Interpreter {
+            program: vec![IncrementPointer],
+            pointer: MEMORY_SIZE - 1,
+            ..Default::default()
+        };
+
+        assert!(interpreter.run().is_err());
+    }
+
+    #[test]
+    fn detect_repeats_works() {
+        // MoO MOO moO mOo moo - loops forever without changing anything
+        let program = vec![
+            IncrementByte,
+            BeginLoop,
+            IncrementPointer,
+            DecrementPointer,
+            EndLoop,
+        ];
+        let interpreter = Interpreter::new(program).detect_repeats(true);
+        assert!(interpreter.run().is_err());
+
+        // MoO MoO MoO MOO moO MoO mOo MOo moo - ends
+        let program = vec![
+            IncrementByte,
+            IncrementByte,
+            IncrementByte,
+            BeginLoop,
+            IncrementPointer,
+            IncrementByte,
+            DecrementPointer,
+            DecrementByte,
+            EndLoop,
+        ];
+        let state = Interpreter::new(program)
+            .detect_repeats(true)
+            .run()
+            .unwrap();
+        assert_eq!(state.memory[..2], [0, 3]);
+    }
+
+    #[test]
+    fn step_works() {
+        // oom MOO OOM MOo moo Moo
+        let mut interpreter = Interpreter::new(vec![
+            ReadStdin,
+            BeginLoop,
+            WriteStdout,
+            DecrementByte,
+            EndLoop,
+            ReadOrWrite,
+        ]);
+
+        assert!(matches!(interpreter.step(), Event::NeedInput));
+        interpreter.push_input(b"2\nA");
+        assert!(matches!(interpreter.step(), Event::Stepped));
+        assert!(matches!(interpreter.step(), Event::Stepped));
+        assert!(matches!(interpreter.step(), Event::Output(output) if output == b"2"));
+        assert!(matches!(interpreter.step(), Event::Stepped));
+        assert!(matches!(
+            interpreter.step(),
+            Event::Jumped { from: 4, to: 2 }
+        ));
+        assert_eq!(interpreter.program_counter(), 2);
+        assert!(matches!(interpreter.step(), Event::Output(output) if output == b"1"));
+        assert!(matches!(interpreter.step(), Event::Stepped));
+        assert!(matches!(interpreter.step(), Event::Stepped));
+        assert!(matches!(interpreter.step(), Event::Stepped));
+        assert_eq!(interpreter.memory()[0], 65);
+        assert!(matches!(interpreter.step(), Event::Halted));
+    }
+
+    #[test]
+    fn step_reports_errors() {
+        // mOo
+        let mut interpreter = Interpreter::new(vec![DecrementPointer]);
+
+        assert!(matches!(interpreter.step(), Event::Error(_)));
+        assert_eq!(interpreter.program_counter(), 0);
+    }
+
+    #[test]
+    fn run_can_be_interrupted() {
+        // MoO MoO MOO MOo moo oom
+        let program = vec![
+            IncrementByte,
+            IncrementByte,
+            BeginLoop,
+            DecrementByte,
+            EndLoop,
+            ReadStdin,
+        ];
+        let flag = Arc::new(AtomicBool::new(true));
+        let mut interpreter = Interpreter::new(program).interrupt_on(flag.clone());
+
+        // Stopped when `moo` jumps back.
+        assert!(interpreter.run_with(&mut &b"7\n"[..], &mut vec![]).is_err());
+        assert_eq!(interpreter.program_counter(), 4);
+        assert_eq!(interpreter.memory()[0], 1);
+        flag.store(false, Ordering::Relaxed);
+        interpreter.run_with(&mut &b"7\n"[..], &mut vec![]).unwrap();
+        assert_eq!(interpreter.memory()[0], 7);
+
+        // Stopped before reading.
+        let mut interpreter = Interpreter::new(vec![ReadStdin]).interrupt_on(Arc::new(true.into()));
+        assert!(interpreter.run_with(&mut &b"7\n"[..], &mut vec![]).is_err());
+        assert_eq!(interpreter.input_position(), 0);
+    }
+
+    #[test]
+    fn interpreter_is_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<Interpreter>();
+    }
+
+    #[test]
+    fn travel_works() {
+        // oom MOO moO MoO mOo MOo moo MMM moO MMM
+        let program = vec![
+            ReadStdin,
+            BeginLoop,
+            IncrementPointer,
+            IncrementByte,
+            DecrementPointer,
+            DecrementByte,
+            EndLoop,
+            CopyOrPaste,
+            IncrementPointer,
+            CopyOrPaste,
+        ];
+        let mut interpreter = Interpreter::new(program).record(true);
+        interpreter.push_input(b"30\n");
+        let mut states = vec![];
+        while !matches!(interpreter.step(), Event::Halted) {
+            states.push(interpreter.clone());
+        }
+        let steps = interpreter.history().unwrap().steps();
+        assert_eq!(steps as usize, states.len());
+
+        let same = |a: &Interpreter, b: &Interpreter| {
+            a.memory[..3] == b.memory[..3]
+                && a.pointer == b.pointer
+                && a.program_counter == b.program_counter
+                && a.register == b.register
+                && a.input == b.input
+        };
+        assert!(interpreter.step_back().unwrap());
+        assert!(same(&interpreter, &states[states.len() - 2]));
+        assert_eq!(
+            interpreter.history().unwrap().last_write(1).unwrap().step,
+            steps - 7
+        );
+
+        // The oldest steps are out of the undo log, so they are replayed.
+        interpreter.history.as_mut().unwrap().log.drain(..100);
+        interpreter.travel(50).unwrap();
+        assert!(same(&interpreter, &states[49]));
+        interpreter.travel(0).unwrap();
+        assert_eq!(interpreter.input, b"30\n");
+        assert_eq!(interpreter.program_counter, 0);
+        assert!(!interpreter.step_back().unwrap());
+
+        while !matches!(interpreter.step(), Event::Halted) {}
+        assert!(same(&interpreter, states.last().unwrap()));
+    }
 }
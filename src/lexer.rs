@@ -1,15 +1,45 @@
-use std::{
-    fs::File,
-    io::{Cursor, Read},
-    path::PathBuf,
-};
+use std::{fs::File, io::Read, ops::Range, path::PathBuf};
 
-use crate::instruction::{AsInstruction, Instruction};
+use crate::{
+    dialect::{Command, Dialect, Semantics},
+    errors::LexError,
+    instruction::{AsInstruction, Instruction},
+};
 
-const TOKEN_SIZE: usize = 3;
+/// UTF-8 byte order mark.
+const BOM: &[u8] = b"\xef\xbb\xbf";
 
 pub struct Lexer {
     bytes: Vec<u8>,
+    /// Whether the source started with a byte order mark, which isn't part of `bytes`.
+    bom: bool,
+    dialect: Dialect,
+    strict: bool,
+    comment: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+/// A token found in the source.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Token {
+    /// Byte offset of the token.
+    pub offset: usize,
+    /// Length of the token in bytes.
+    pub length: usize,
+    pub command: Command,
+}
+
+/// A problem in the source which doesn't prevent it from running.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
 }
 
 impl Lexer {
@@ -18,44 +48,368 @@ impl Lexer {
         log::info!("Reading bytes from {}", path.display());
 
         let mut bytes = vec![];
-        let mut file = File::open(&path).map_err(|e| {
+        let mut file = File::open(&path).inspect_err(|_| {
             log::error!("Failed to open `{}`", path.display());
-            e
         })?;
-        file.read_to_end(&mut bytes).map_err(|e| {
+        file.read_to_end(&mut bytes).inspect_err(|e| {
             log::error!("Failed to read bytes - cause {e}");
-            e
         })?;
         log::debug!("{:?}", bytes);
-        Ok(Self { bytes })
+        Ok(Self::from_bytes(bytes))
+    }
+
+    pub fn from_bytes(mut bytes: Vec<u8>) -> Self {
+        let bom = bytes.starts_with(BOM);
+        if bom {
+            log::debug!("Skipping the byte order mark");
+            bytes.drain(..BOM.len());
+        }
+        Self {
+            bytes,
+            bom,
+            dialect: Dialect::cow(),
+            strict: false,
+            comment: None,
+        }
+    }
+
+    /// Sets the dialect the source is written in. Defaults to COW.
+    pub fn dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// In strict mode, tokens must be delimited by whitespace or other non-letters, so that e.g.
+    /// `moon` or `MOOD` in prose are not read as instructions. Runs of tokens such as `MoOMoOMoO`
+    /// are ignored too.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Ignores everything between `start` and `end`, e.g. `[[` and `]]`.
+    pub fn comment(mut self, start: &str, end: &str) -> Self {
+        self.comment = Some((start.into(), end.into()));
+        self
+    }
+
+    pub fn lex(self) -> Result<Vec<Instruction>, LexError> {
+        if let Err(e) = std::str::from_utf8(&self.bytes) {
+            let (line, column) = self.line_column(e.valid_up_to());
+            return Err(LexError::InvalidUtf8 { line, column });
+        }
+
+        let commands = self
+            .tokens()
+            .into_iter()
+            .map(|token| token.command)
+            .collect::<Vec<_>>();
+        log::info!("Lexical analysis completed successfully.");
+
+        if self.dialect.semantics() != Semantics::Cow {
+            log::debug!(
+                "Lowering {} commands of `{}`",
+                commands.len(),
+                self.dialect.name()
+            );
+        }
+        let program = self.dialect.lower(commands)?;
+
+        log::debug!("Results of lexical analysis: {:?}", program);
+
+        Ok(program)
+    }
+
+    /// Returns the source without the byte order mark.
+    pub fn source(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Returns the byte order mark the source started with, if any.
+    pub fn bom(&self) -> Option<&'static [u8]> {
+        self.bom.then_some(BOM)
+    }
+
+    /// Returns the comment delimiters set with [`Lexer::comment`].
+    pub fn comment_delimiters(&self) -> Option<(&[u8], &[u8])> {
+        self.comment
+            .as_ref()
+            .map(|(start, end)| (start.as_slice(), end.as_slice()))
+    }
+
+    /// Creates a lexer for another source with the same options.
+    pub fn with_source(&self, bytes: Vec<u8>) -> Self {
+        Self {
+            dialect: self.dialect.clone(),
+            comment: self.comment.clone(),
+            strict: self.strict,
+            ..Self::from_bytes(bytes)
+        }
+    }
+
+    pub fn tokens(&self) -> Vec<Token> {
+        self.scan(self.strict)
     }
 
-    pub fn lex(self) -> Result<Vec<Instruction>, std::io::Error> {
-        let mut buffer = [0; TOKEN_SIZE];
-        let length = self.bytes.len();
-        let mut cursor = Cursor::new(self.bytes);
-        let mut program = vec![];
+    /// Lists the places where the permissive and the strict lexers disagree.
+    pub fn warnings(&self) -> Vec<Warning> {
+        let permissive = self.scan(false);
+        let strict = self.scan(true);
+        let starts = self.line_starts();
+        // Near misses are only looked for in COW itself, as a dialect named `cow` may have other
+        // tokens.
+        let near_misses = if self.dialect == Dialect::cow() {
+            self.near_misses(&starts)
+        } else {
+            vec![]
+        };
+        // A near miss explains the disagreement better.
+        let explained = |token: &Token| {
+            let index = near_misses.partition_point(|(word, _)| word.end <= token.offset);
+            near_misses
+                .get(index)
+                .is_some_and(|(word, _)| word.contains(&token.offset))
+        };
+        let mut warnings = vec![];
 
+        // Both lists are sorted by offset, so the tokens found by only one are found side by side.
+        let (mut permissive, mut strict) = (permissive.iter().peekable(), strict.iter().peekable());
         loop {
-            if cursor.position() >= (length - 2) as u64 {
-                log::info!("Lexical analysis completed successfully.");
-                break;
+            let (token, in_strict) = match (permissive.peek(), strict.peek()) {
+                (Some(a), Some(b)) if a == b => {
+                    permissive.next();
+                    strict.next();
+                    continue;
+                }
+                (Some(a), Some(b)) if b.offset < a.offset => (strict.next().unwrap(), true),
+                (Some(_), _) => (permissive.next().unwrap(), false),
+                (None, Some(_)) => (strict.next().unwrap(), true),
+                (None, None) => break,
+            };
+            if explained(token) {
+                continue;
+            }
+            let message = match (in_strict, self.strict) {
+                (false, true) => "is glued to other letters and ignored in strict mode",
+                (false, false) => "is glued to other letters and would be ignored in strict mode",
+                (true, true) => "would be read differently in permissive mode",
+                (true, false) => "is read differently in permissive mode",
+            };
+            warnings.push(self.warning(&starts, token.offset, token.length, message));
+        }
+        warnings.extend(near_misses.into_iter().map(|(_, warning)| warning));
+        warnings.sort_by_key(|warning| (warning.line, warning.column));
+
+        warnings
+    }
+
+    /// Finds words which look like broken COW tokens, such as `MoOo`, `m0o` or `OoO`. The
+    /// dialect must be COW. `starts` are the offsets of the lines, see [`Lexer::line_starts`].
+    fn near_misses(&self, starts: &[usize]) -> Vec<(Range<usize>, Warning)> {
+        let bytes = &self.bytes;
+        let mut position = 0;
+        let mut warnings = vec![];
+
+        while position < bytes.len() {
+            if let Some(end) = self.comment_end(position) {
+                position = end;
+                continue;
+            }
+            let length = bytes[position..]
+                .iter()
+                .take_while(|byte| byte.is_ascii_alphanumeric())
+                .count();
+            if length == 0 {
+                position += 1;
+                continue;
             }
 
-            cursor.read_exact(&mut buffer)?;
+            let word = &bytes[position..][..length];
+            // A single `o` or `O` is most likely prose.
+            let cow_like = length > 1
+                && word.iter().all(|byte| b"mMoO0".contains(byte))
+                && word.iter().any(|byte| byte.is_ascii_alphabetic());
+            if cow_like {
+                let message = if word.contains(&b'0') {
+                    Some("has the digit `0` instead of the letter `o` or `O`".into())
+                } else if length % 3 != 0 {
+                    Some(format!("is {length} letters long, but COW tokens have 3"))
+                } else {
+                    word.chunks(3)
+                        .find(|chunk| self.dialect.match_at(chunk).is_none())
+                        .and_then(|chunk| {
+                            let candidates = (0..12)
+                                .filter_map(|code: i32| code.as_instruction())
+                                .map(Instruction::as_str)
+                                .filter(|token| token.as_bytes().eq_ignore_ascii_case(chunk))
+                                .map(|token| format!("`{token}`"))
+                                .collect::<Vec<_>>();
+                            // Such as `mom`, which is most likely prose.
+                            (!candidates.is_empty()).then(|| {
+                                format!(
+                                    "has a wrong case mix `{}`, did you mean {}?",
+                                    String::from_utf8_lossy(chunk),
+                                    candidates.join(" or ")
+                                )
+                            })
+                        })
+                };
+                if let Some(message) = message {
+                    warnings.push((
+                        position..position + length,
+                        self.warning(starts, position, length, &message),
+                    ));
+                }
+            }
+            position += length;
+        }
+
+        warnings
+    }
+
+    fn warning(&self, starts: &[usize], offset: usize, length: usize, message: &str) -> Warning {
+        let line = starts.partition_point(|&start| start <= offset);
+        let column = offset - starts[line - 1] + 1;
+        let text = String::from_utf8_lossy(&self.bytes[offset..][..length]);
+        Warning {
+            line,
+            column,
+            message: format!("`{text}` {message}"),
+        }
+    }
 
-            if let Some(instruction) = buffer.as_instruction() {
-                program.push(instruction)
+    /// Returns the byte offsets of the beginnings of the lines.
+    fn line_starts(&self) -> Vec<usize> {
+        [0].into_iter()
+            .chain(
+                self.bytes
+                    .iter()
+                    .enumerate()
+                    .filter(|&(_, &byte)| byte == b'\n')
+                    .map(|(offset, _)| offset + 1),
+            )
+            .collect()
+    }
+
+    /// Returns the 1-based line and column of `offset`.
+    pub fn line_column(&self, offset: usize) -> (usize, usize) {
+        let before = &self.bytes[..offset];
+        let line = before.iter().filter(|&&byte| byte == b'\n').count() + 1;
+        let line_start = before
+            .iter()
+            .rposition(|&byte| byte == b'\n')
+            .map_or(0, |newline| newline + 1);
+        (line, offset - line_start + 1)
+    }
+
+    /// Returns the 1-based line and column of each instruction [`Lexer::lex`] returns, or `None`
+    /// if the dialect isn't lexed into instructions one to one.
+    pub fn instruction_positions(&self) -> Option<Vec<(usize, usize)>> {
+        if self.dialect.semantics() != Semantics::Cow {
+            return None;
+        }
+        let (mut line, mut line_start, mut position) = (1, 0, 0);
+        let positions = self
+            .tokens()
+            .into_iter()
+            .map(|token| {
+                for (offset, &byte) in self.bytes[position..token.offset].iter().enumerate() {
+                    if byte == b'\n' {
+                        line += 1;
+                        line_start = position + offset + 1;
+                    }
+                }
+                position = token.offset;
+                (line, token.offset - line_start + 1)
+            })
+            .collect();
+        Some(positions)
+    }
+
+    /// Returns the length in bytes of each instruction [`Lexer::lex`] returns, next to
+    /// [`Lexer::instruction_positions`].
+    pub fn instruction_lengths(&self) -> Option<Vec<usize>> {
+        if self.dialect.semantics() != Semantics::Cow {
+            return None;
+        }
+        Some(self.tokens().iter().map(|token| token.length).collect())
+    }
+
+    fn scan(&self, strict: bool) -> Vec<Token> {
+        let bytes = &self.bytes;
+        let mut position = 0;
+        let mut tokens = vec![];
+
+        while position < bytes.len() {
+            if let Some(end) = self.comment_end(position) {
+                position = end;
+                continue;
+            }
+
+            if strict {
+                if let Some(token) = self.match_delimited(position) {
+                    position += token.length;
+                    tokens.push(token);
+                } else if bytes[position].is_ascii_alphabetic() {
+                    // Skip the whole word.
+                    position += bytes[position..]
+                        .iter()
+                        .take_while(|byte| byte.is_ascii_alphabetic())
+                        .count();
+                } else {
+                    position += 1;
+                }
+            } else if let Some((length, command)) = self.dialect.match_at(&bytes[position..]) {
+                tokens.push(Token {
+                    offset: position,
+                    length,
+                    command,
+                });
+                position += length;
             } else {
-                let current_position = cursor.position();
-                cursor.set_position(current_position - 2);
+                position += 1;
             }
         }
 
-        log::debug!("Results of lexical analysis: {:?}", program);
+        tokens
+    }
 
-        Ok(program)
+    /// Returns the offset right after the comment starting at `position`, if any.
+    pub(crate) fn comment_end(&self, position: usize) -> Option<usize> {
+        let (start, end) = self.comment.as_ref()?;
+        if start.is_empty() || !self.bytes[position..].starts_with(start) {
+            return None;
+        }
+        let body = position + start.len();
+        Some(match find(&self.bytes[body..], end) {
+            Some(length) => body + length + end.len(),
+            None => self.bytes.len(),
+        })
+    }
+
+    /// Matches the token at `position`, the start of a word, if no letter is glued after it.
+    fn match_delimited(&self, position: usize) -> Option<Token> {
+        let bytes = &self.bytes;
+        let (length, command) = self.dialect.match_at(&bytes[position..])?;
+        let end = position + length;
+        let glued = end < bytes.len()
+            && bytes[end - 1].is_ascii_alphabetic()
+            && bytes[end].is_ascii_alphabetic();
+        (!glued).then_some(Token {
+            offset: position,
+            length,
+            command,
+        })
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return Some(0);
     }
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
 }
 
 #[cfg(test)]
@@ -71,6 +425,10 @@ mod tests {
                 0x4d, 0x4f, 0x4f, 0x20, 0x4f, 0x4f, 0x4f, 0x20, 0x4d, 0x4d, 0x4d, 0x20, 0x4f, 0x4f,
                 0x4d, 0x20, 0x6f, 0x6f, 0x6d,
             ],
+            bom: false,
+            dialect: Dialect::cow(),
+            strict: false,
+            comment: None,
         };
         assert_eq!(
             lexer.lex().unwrap(),
@@ -90,4 +448,217 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn lex_with_dialect_works() {
+        let lexer = Lexer {
+            bytes: b"Ook. Ook. Ook! Ook?\nOok! Ook! Ook? Ook!".to_vec(),
+            bom: false,
+            dialect: Dialect::ook(),
+            strict: false,
+            comment: None,
+        };
+        assert_eq!(
+            lexer.lex().unwrap(),
+            vec![
+                Instruction::IncrementByte,
+                Instruction::BeginLoop,
+                Instruction::DecrementByte,
+                Instruction::EndLoop,
+            ]
+        );
+    }
+
+    #[test]
+    fn strict_lex_works() {
+        let lexer = Lexer {
+            bytes: b"moon MOOD MoOMoO MoO [[ Moo ]] OOO".to_vec(),
+            bom: false,
+            dialect: Dialect::cow(),
+            strict: false,
+            comment: None,
+        }
+        .comment("[[", "]]");
+
+        assert_eq!(
+            lexer.strict(true).lex().unwrap(),
+            vec![Instruction::IncrementByte, Instruction::SetZero]
+        );
+    }
+
+    #[test]
+    fn warnings_works() {
+        let lexer = Lexer {
+            bytes: b"MoO\nthe moon".to_vec(),
+            bom: false,
+            dialect: Dialect::cow(),
+            strict: false,
+            comment: None,
+        };
+
+        assert_eq!(
+            lexer.warnings(),
+            vec![Warning {
+                line: 2,
+                column: 5,
+                message: "`moo` is glued to other letters and would be ignored in strict mode"
+                    .into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn near_miss_warnings_works() {
+        let lexer = Lexer {
+            bytes: b"MoOo m0o\nOoO MoOMoO Mom moM".to_vec(),
+            bom: false,
+            dialect: Dialect::cow(),
+            strict: true,
+            comment: None,
+        };
+
+        assert_eq!(
+            lexer.warnings(),
+            vec![
+                Warning {
+                    line: 1,
+                    column: 1,
+                    message: "`MoOo` is 4 letters long, but COW tokens have 3".into(),
+                },
+                Warning {
+                    line: 1,
+                    column: 6,
+                    message: "`m0o` has the digit `0` instead of the letter `o` or `O`".into(),
+                },
+                Warning {
+                    line: 2,
+                    column: 1,
+                    message: "`OoO` has a wrong case mix `OoO`, did you mean `OOO`?".into(),
+                },
+                Warning {
+                    line: 2,
+                    column: 5,
+                    message: "`MoO` is glued to other letters and ignored in strict mode".into(),
+                },
+                Warning {
+                    line: 2,
+                    column: 8,
+                    message: "`MoO` is glued to other letters and ignored in strict mode".into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn near_misses_are_only_found_in_cow() {
+        let tokens = vec![(b"muu".to_vec(), Command::Cow(Instruction::EndLoop))];
+        let lexer = Lexer::from_bytes(b"muu OoO".to_vec())
+            .dialect(Dialect::new("cow", Semantics::Cow, tokens).unwrap());
+
+        assert_eq!(lexer.warnings(), vec![]);
+    }
+
+    #[test]
+    fn lex_tiny_inputs_works() {
+        assert_eq!(Lexer::from_bytes(vec![]).lex().unwrap(), vec![]);
+        assert_eq!(Lexer::from_bytes(b"M".to_vec()).lex().unwrap(), vec![]);
+        assert_eq!(Lexer::from_bytes(BOM.to_vec()).lex().unwrap(), vec![]);
+        assert_eq!(
+            Lexer::from_bytes(b"MoO Mo".to_vec()).lex().unwrap(),
+            vec![Instruction::IncrementByte]
+        );
+        assert!(Lexer::from_bytes(b"Ook. Ook".to_vec())
+            .dialect(Dialect::ook())
+            .lex()
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn lex_skips_byte_order_mark() {
+        let lexer = Lexer::from_bytes(b"\xef\xbb\xbfMoO\r\nmoOo".to_vec());
+
+        assert_eq!(
+            lexer.warnings(),
+            vec![Warning {
+                line: 2,
+                column: 1,
+                message: "`moOo` is 4 letters long, but COW tokens have 3".into(),
+            }]
+        );
+        assert_eq!(
+            lexer.lex().unwrap(),
+            vec![Instruction::IncrementByte, Instruction::IncrementPointer]
+        );
+    }
+
+    #[test]
+    fn lex_rejects_invalid_utf8() {
+        let lexer = Lexer::from_bytes(b"MoO\r\nMoO \xff\xfe".to_vec());
+        assert_eq!(
+            lexer.lex().unwrap_err(),
+            LexError::InvalidUtf8 { line: 2, column: 5 }
+        );
+
+        // UTF-16 with a byte order mark.
+        let lexer = Lexer::from_bytes(b"\xff\xfeM\0o\0O\0".to_vec());
+        assert_eq!(
+            lexer.lex().unwrap_err(),
+            LexError::InvalidUtf8 { line: 1, column: 1 }
+        );
+    }
+
+    #[test]
+    fn instruction_positions_works() {
+        let lexer = Lexer::from_bytes(b"MoO moO\r\n\n  Moo".to_vec());
+        assert_eq!(
+            lexer.instruction_positions(),
+            Some(vec![(1, 1), (1, 5), (3, 3)])
+        );
+        assert_eq!(lexer.instruction_lengths(), Some(vec![3, 3, 3]));
+
+        let dialect = Dialect::new(
+            "long",
+            Semantics::Cow,
+            vec![(b"MOOO".to_vec(), Command::Cow(Instruction::WriteStdout))],
+        )
+        .unwrap();
+        let lexer = Lexer::from_bytes(b"MOOO MOOO".to_vec()).dialect(dialect);
+        assert_eq!(lexer.instruction_lengths(), Some(vec![4, 4]));
+
+        let lexer = Lexer::from_bytes(b"+".to_vec()).dialect(Dialect::brainfuck());
+        assert_eq!(lexer.instruction_positions(), None);
+        assert_eq!(lexer.instruction_lengths(), None);
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn never_panics(
+            bytes in proptest::collection::vec(proptest::prelude::any::<u8>(), 0..64),
+            strict: bool,
+            dialect in 0..3,
+        ) {
+            let dialect = match dialect {
+                0 => Dialect::cow(),
+                1 => Dialect::brainfuck(),
+                _ => Dialect::ook(),
+            };
+            let lexer = Lexer::from_bytes(bytes)
+                .dialect(dialect)
+                .strict(strict)
+                .comment("[[", "]]");
+            lexer.warnings();
+            lexer.tokens();
+            let _ = lexer.lex();
+        }
+
+        #[test]
+        fn never_panics_on_cow_like_text(source in "[mMoO0 \r\n\\[\\]]{0,64}", strict: bool) {
+            let lexer = Lexer::from_bytes(source.into_bytes())
+                .strict(strict)
+                .comment("[[", "]]");
+            lexer.warnings();
+            proptest::prop_assert!(lexer.lex().is_ok());
+        }
+    }
 }
@@ -1,4 +1,24 @@
+pub mod ast;
+pub mod bounds;
+pub mod coverage;
+pub mod dap;
+pub mod debugger;
+pub mod dialect;
 pub mod errors;
+pub mod format;
+pub mod history;
 pub mod instruction;
 pub mod interpreter;
 pub mod lexer;
+pub mod lint;
+pub mod lsp;
+pub mod message;
+pub mod observer;
+pub mod profile;
+pub mod repl;
+pub mod snapshot;
+pub mod syntax;
+pub mod trace;
+pub mod translate;
+pub mod tui;
+pub mod view;
@@ -0,0 +1,621 @@
+use std::{collections::HashMap, ops::Range};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    ast::{self, Block, Node, Visitor},
+    bounds::{self, Interval},
+    instruction::{
+        AsInstruction,
+        Instruction::{self, *},
+    },
+    interpreter::MEMORY_SIZE,
+};
+
+/// What to do when a rule finds something.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+impl Level {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Allow => "allow",
+            Self::Warn => "warning",
+            Self::Deny => "error",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Rule {
+    pub id: &'static str,
+    pub level: Level,
+    pub description: &'static str,
+}
+
+pub const CANCELLING_PAIR: &Rule = &Rule {
+    id: "cancelling-pair",
+    level: Level::Warn,
+    description: "`MoO MOo` or `moO mOo` in a row, which do nothing together",
+};
+pub const DEAD_LOOP: &Rule = &Rule {
+    id: "dead-loop",
+    level: Level::Warn,
+    description: "`OOO` right before `MOO`, so the loop never runs",
+};
+pub const DANGLING_REGISTER: &Rule = &Rule {
+    id: "dangling-register",
+    level: Level::Warn,
+    description: "an odd number of `MMM`, which leaves a value in the register",
+};
+pub const INVALID_EXECUTE: &Rule = &Rule {
+    id: "invalid-execute",
+    level: Level::Deny,
+    description: "`mOO` with a value known to be 3 or not an instruction code",
+};
+pub const UNREACHABLE_CODE: &Rule = &Rule {
+    id: "unreachable-code",
+    level: Level::Warn,
+    description: "code after a loop which never ends",
+};
+pub const INFINITE_LOOP: &Rule = &Rule {
+    id: "infinite-loop",
+    level: Level::Deny,
+    description: "a loop which is entered and never changes the memory block it tests",
+};
+pub const STUCK_LOOP: &Rule = &Rule {
+    id: "stuck-loop",
+    level: Level::Warn,
+    description:
+        "a loop which never changes the memory block it tests, so it never ends once entered",
+};
+pub const TAPE_BOUNDS: &Rule = &Rule {
+    id: "tape-bounds",
+    level: Level::Warn,
+    description: "a pointer move which may leave the memory",
+};
+
+pub const RULES: &[&Rule] = &[
+    CANCELLING_PAIR,
+    DEAD_LOOP,
+    DANGLING_REGISTER,
+    INVALID_EXECUTE,
+    UNREACHABLE_CODE,
+    INFINITE_LOOP,
+    STUCK_LOOP,
+    TAPE_BOUNDS,
+];
+
+/// Something a rule found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub rule: &'static Rule,
+    pub level: Level,
+    /// Indexes of the instructions concerned.
+    pub range: Range<usize>,
+    pub message: String,
+}
+
+/// Runs the rules in [`RULES`] at their configured levels.
+#[derive(Debug, Default)]
+pub struct Linter {
+    levels: HashMap<&'static str, Level>,
+}
+
+impl Linter {
+    /// Overrides the level of the rule called `id`.
+    pub fn level(mut self, id: &str, level: Level) -> Result<Self> {
+        match RULES.iter().find(|rule| rule.id == id) {
+            Some(rule) => {
+                self.levels.insert(rule.id, level);
+                Ok(self)
+            }
+            None => bail!("Unknown lint rule `{id}`"),
+        }
+    }
+
+    fn level_of(&self, rule: &Rule) -> Level {
+        self.levels.get(rule.id).copied().unwrap_or(rule.level)
+    }
+
+    /// Returns the diagnostics of every rule which isn't allowed, in program order.
+    pub fn lint(&self, program: &[Instruction]) -> Vec<Diagnostic> {
+        let mut diagnostics = vec![];
+        let mut report = |rule: &'static Rule, range: Range<usize>, message: String| {
+            let level = self.level_of(rule);
+            if level != Level::Allow {
+                diagnostics.push(Diagnostic {
+                    rule,
+                    level,
+                    range,
+                    message,
+                });
+            }
+        };
+
+        let mut index = 0;
+        while index + 1 < program.len() {
+            let pair = (program[index], program[index + 1]);
+            let cancelling = matches!(
+                pair,
+                (IncrementByte, DecrementByte)
+                    | (DecrementByte, IncrementByte)
+                    | (IncrementPointer, DecrementPointer)
+                    | (DecrementPointer, IncrementPointer)
+            );
+            // Right after `MOO` or before `moo`, the pair changes which commands loops match.
+            let padding = (index > 0 && program[index - 1] == BeginLoop)
+                || program.get(index + 2) == Some(&EndLoop);
+            if cancelling && !padding {
+                report(
+                    CANCELLING_PAIR,
+                    index..index + 2,
+                    format!("`{} {}` cancel each other out", pair.0, pair.1),
+                );
+                index += 2;
+            } else {
+                index += 1;
+            }
+        }
+
+        for index in 1..program.len() {
+            if program[index - 1] == SetZero && program[index] == BeginLoop {
+                report(
+                    DEAD_LOOP,
+                    index - 1..index + 1,
+                    "`MOO` right after `OOO` always skips its loop".into(),
+                );
+            }
+        }
+
+        let copies: Vec<_> = (0..program.len())
+            .filter(|&index| program[index] == CopyOrPaste)
+            .collect();
+        if let Some(&last) = copies.last().filter(|_| copies.len() % 2 == 1) {
+            report(
+                DANGLING_REGISTER,
+                last..last + 1,
+                format!(
+                    "`MMM` is used an odd number of times ({}), so the register may still hold a \
+                     value at the end",
+                    copies.len()
+                ),
+            );
+        }
+
+        let block = ast::parse(program);
+        let mut values = Values::new();
+        values.visit_block(0, &block);
+        let bounds = bounds::analyze_with(program, MEMORY_SIZE, &values.executes);
+        let mut stuck = Stuck {
+            pointers: &bounds.pointers,
+            loops: vec![],
+        };
+        stuck.visit_block(0, &block);
+        for (rule, range, message) in values.findings {
+            report(rule, range, message);
+        }
+        for range in stuck.loops {
+            if values.infinite_loops.contains(&range.start) {
+                continue;
+            }
+            report(
+                STUCK_LOOP,
+                range,
+                "The loop never changes the memory block it tests, so it never ends once entered"
+                    .into(),
+            );
+        }
+
+        let mut violations = bounds
+            .violations
+            .into_iter()
+            // Reported where the pointer was given up on.
+            .filter(|violation| violation.certain || !violation.widened)
+            .peekable();
+        while let Some(violation) = violations.next() {
+            // A run of moves is reported once.
+            let mut end = violation.index + 1;
+            while let Some(next) = violations.next_if(|next| {
+                next.index == end
+                    && next.instruction == violation.instruction
+                    && next.certain == violation.certain
+            }) {
+                end = next.index + 1;
+            }
+            let pointer = violation.pointer;
+            let message = if violation.certain {
+                format!(
+                    "`{}` always moves the pointer off the memory",
+                    violation.instruction
+                )
+            } else {
+                format!(
+                    "`{}` may move the pointer off the memory, as it is between {} and {} here",
+                    violation.instruction, pointer.low, pointer.high
+                )
+            };
+            report(TAPE_BOUNDS, violation.index..end, message);
+        }
+        for index in bounds.widened {
+            report(
+                TAPE_BOUNDS,
+                index..index + 1,
+                "A loop moves the pointer by an unknown amount before reaching here, so it may \
+                 move off the memory"
+                    .into(),
+            );
+        }
+
+        diagnostics.sort_by_key(|diagnostic| diagnostic.range.start);
+        diagnostics
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Register {
+    Empty,
+    /// Holds a value, which may be unknown.
+    Full(Option<i32>),
+    Unknown,
+}
+
+/// What is known about the machine at some point of the program.
+#[derive(Debug, Clone)]
+struct State {
+    /// `None` if the pointer isn't known anymore, e.g. after an unbalanced loop.
+    pointer: Option<isize>,
+    /// Values of the memory blocks which differ from `default`.
+    cells: HashMap<isize, Option<i32>>,
+    default: Option<i32>,
+    register: Register,
+}
+
+impl State {
+    fn get(&self) -> Option<i32> {
+        let pointer = self.pointer?;
+        self.cells.get(&pointer).copied().unwrap_or(self.default)
+    }
+
+    fn set(&mut self, value: Option<i32>) {
+        if let Some(pointer) = self.pointer {
+            self.cells.insert(pointer, value);
+        }
+    }
+
+    /// Forgets every memory block and the register, but keeps the pointer.
+    fn forget(&mut self) {
+        self.cells.clear();
+        self.default = None;
+        self.register = Register::Unknown;
+    }
+}
+
+/// Tracks values known at compile time through structured code.
+///
+/// Stops at the first command which may jump somewhere unstructured, such as `mOO` with an
+/// unknown value or a `moo` without a loop, since a state computed for one path wouldn't hold
+/// for the others anymore.
+struct Values {
+    /// `None` if the current point is never reached.
+    state: Option<State>,
+    aborted: bool,
+    /// The loop which made the rest of the block unreachable, if any.
+    infinite: Option<usize>,
+    /// Loops reported as [`INFINITE_LOOP`].
+    infinite_loops: Vec<usize>,
+    findings: Vec<(&'static Rule, Range<usize>, String)>,
+    /// What the `mOO`s with a known value run, see [`bounds::analyze_with`].
+    executes: HashMap<usize, Option<Instruction>>,
+}
+
+impl Values {
+    fn new() -> Self {
+        Self {
+            state: Some(State {
+                pointer: Some(0),
+                cells: HashMap::new(),
+                default: Some(0),
+                register: Register::Empty,
+            }),
+            aborted: false,
+            infinite: None,
+            infinite_loops: vec![],
+            findings: vec![],
+            executes: HashMap::new(),
+        }
+    }
+
+    fn apply(&mut self, index: usize, instruction: Instruction) {
+        let Some(state) = self.state.as_mut() else {
+            return;
+        };
+        let value = state.get();
+        match instruction {
+            IncrementByte => state.set(value.map(|value| value.wrapping_add(1))),
+            DecrementByte => state.set(value.map(|value| value.wrapping_sub(1))),
+            IncrementPointer => state.pointer = state.pointer.map(|pointer| pointer + 1),
+            DecrementPointer => state.pointer = state.pointer.map(|pointer| pointer - 1),
+            SetZero => state.set(Some(0)),
+            ReadOrWrite if value == Some(0) || value.is_none() => state.set(None),
+            ReadOrWrite | WriteStdout => {}
+            ReadStdin => state.set(None),
+            CopyOrPaste => match state.register {
+                Register::Empty => state.register = Register::Full(value),
+                Register::Full(register) => {
+                    state.set(register);
+                    state.register = Register::Empty;
+                }
+                Register::Unknown => state.set(None),
+            },
+            ExecuteValue => match value.map(|value| (value, value.as_instruction())) {
+                Some((value, None | Some(ExecuteValue))) => {
+                    self.findings.push((
+                        INVALID_EXECUTE,
+                        index..index + 1,
+                        format!("`mOO` runs with {value}, which stops the program with an error"),
+                    ));
+                    self.executes.insert(index, None);
+                    self.state = None;
+                }
+                Some((_, Some(instruction))) if !instruction.is_loop() => {
+                    self.executes.insert(index, Some(instruction));
+                    self.apply(index, instruction)
+                }
+                _ => self.aborted = true,
+            },
+            BeginLoop | EndLoop => self.aborted = true,
+        }
+    }
+
+    fn enter_loop(&mut self, index: usize, body: &Block) {
+        let Some(state) = self.state.clone() else {
+            return;
+        };
+        let value = state.get();
+        if value == Some(0) {
+            return;
+        }
+        let balanced = delta(body) == Some(0);
+
+        let mut entry = state.clone();
+        entry.forget();
+        if !balanced {
+            entry.pointer = None;
+        }
+        self.state = Some(entry);
+        self.visit_block(index + 1, body);
+        if self.aborted {
+            return;
+        }
+        let completes = self.state.is_some();
+
+        let entered = value.is_some();
+        let stuck = state.pointer.is_some_and(|pointer| {
+            let pointer = pointer as usize;
+            is_stuck(
+                body,
+                Interval {
+                    low: pointer,
+                    high: pointer,
+                },
+            )
+        });
+        if entered && stuck {
+            self.findings.push((
+                INFINITE_LOOP,
+                index..index + body.len() + 2,
+                "The loop is always entered and never changes the memory block it tests".into(),
+            ));
+            self.infinite_loops.push(index);
+        }
+        if entered && (!completes || stuck) {
+            self.infinite.get_or_insert(index);
+            self.state = None;
+            return;
+        }
+        let mut exit = state;
+        exit.forget();
+        if !balanced {
+            exit.pointer = None;
+        }
+        exit.set(Some(0));
+        self.state = Some(exit);
+    }
+}
+
+impl Visitor for Values {
+    fn visit_block(&mut self, mut index: usize, block: &Block) {
+        let end = index + block.len();
+        for node in &block.0 {
+            if self.aborted {
+                return;
+            }
+            if self.state.is_none() {
+                if let Some(infinite) = self.infinite.take() {
+                    self.findings.push((
+                        UNREACHABLE_CODE,
+                        index..end,
+                        format!("The loop at instruction {infinite} never ends"),
+                    ));
+                }
+                return;
+            }
+            match node {
+                Node::Op(instruction) => self.visit_op(index, *instruction),
+                Node::Loop { body } => self.visit_loop(index, body),
+            }
+            index += node.size();
+        }
+    }
+
+    fn visit_op(&mut self, index: usize, instruction: Instruction) {
+        self.apply(index, instruction);
+    }
+
+    fn visit_loop(&mut self, index: usize, body: &Block) {
+        self.enter_loop(index, body);
+    }
+}
+
+/// Returns the net pointer movement of `block`, if known.
+fn delta(block: &Block) -> Option<isize> {
+    block.0.iter().try_fold(0, |total, node| match node {
+        Node::Op(IncrementPointer) => Some(total + 1),
+        Node::Op(DecrementPointer) => Some(total - 1),
+        Node::Op(ExecuteValue | BeginLoop | EndLoop) => None,
+        Node::Op(_) => Some(total),
+        Node::Loop { body } => (delta(body)? == 0).then_some(total),
+    })
+}
+
+/// Finds every loop which never ends once entered.
+struct Stuck<'a> {
+    pointers: &'a [Option<Interval>],
+    loops: Vec<Range<usize>>,
+}
+
+impl Visitor for Stuck<'_> {
+    fn visit_loop(&mut self, index: usize, body: &Block) {
+        if let Some(pointer) = self.pointers[index] {
+            if is_stuck(body, pointer) {
+                self.loops.push(index..index + body.len() + 2);
+            }
+        }
+        ast::walk_loop(self, index, body);
+    }
+}
+
+/// Returns `true` if `body` never changes the memory block its loop tests and can't fail, so
+/// that the loop never ends once entered with the pointer in `pointer`.
+fn is_stuck(body: &Block, pointer: Interval) -> bool {
+    let (low, high) = (pointer.low as isize, pointer.high as isize);
+    let mut offset = 0;
+    for node in &body.0 {
+        match node {
+            Node::Op(IncrementPointer) if high + offset + 1 >= MEMORY_SIZE as isize => {
+                return false
+            }
+            Node::Op(IncrementPointer) => offset += 1,
+            Node::Op(DecrementPointer) if low + offset <= 0 => return false,
+            Node::Op(DecrementPointer) => offset -= 1,
+            // `Moo` only writes while the block isn't 0.
+            Node::Op(IncrementByte | DecrementByte | SetZero | CopyOrPaste | ReadStdin)
+                if offset == 0 =>
+            {
+                return false
+            }
+            Node::Op(ExecuteValue | BeginLoop | EndLoop) | Node::Loop { .. } => return false,
+            Node::Op(_) => {}
+        }
+    }
+    offset == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lint(program: &[Instruction]) -> Vec<(&'static str, Range<usize>)> {
+        Linter::default()
+            .lint(program)
+            .into_iter()
+            .map(|diagnostic| (diagnostic.rule.id, diagnostic.range))
+            .collect()
+    }
+
+    #[test]
+    fn cancelling_pair_works() {
+        // MoO MOo MoO moO mOo - and the padding of `MOO MoO MOo MOO`
+        let program = [
+            IncrementByte,
+            DecrementByte,
+            IncrementByte,
+            IncrementPointer,
+            DecrementPointer,
+            BeginLoop,
+            IncrementByte,
+            DecrementByte,
+            BeginLoop,
+        ];
+
+        assert_eq!(
+            lint(&program),
+            [("cancelling-pair", 0..2), ("cancelling-pair", 3..5)]
+        );
+    }
+
+    #[test]
+    fn dead_loop_and_dangling_register_works() {
+        // MMM OOO MOO MoO moo
+        let program = [CopyOrPaste, SetZero, BeginLoop, IncrementByte, EndLoop];
+
+        assert_eq!(
+            lint(&program),
+            [("dangling-register", 0..1), ("dead-loop", 1..3)]
+        );
+    }
+
+    #[test]
+    fn invalid_execute_works() {
+        // MoO MoO MoO mOO
+        let program = [IncrementByte, IncrementByte, IncrementByte, ExecuteValue];
+        assert_eq!(lint(&program), [("invalid-execute", 3..4)]);
+
+        // MoO MOO MOo moo MOo mOO
+        let program = [
+            IncrementByte,
+            BeginLoop,
+            DecrementByte,
+            EndLoop,
+            DecrementByte,
+            ExecuteValue,
+        ];
+        assert_eq!(lint(&program), [("invalid-execute", 5..6)]);
+
+        // oom mOO - unknown, but it may run `mOo`
+        assert_eq!(lint(&[ReadStdin, ExecuteValue]), [("tape-bounds", 1..2)]);
+    }
+
+    #[test]
+    fn unreachable_code_works() {
+        // MoO MOO moO Moo mOo moo OOM
+        let program = [
+            IncrementByte,
+            BeginLoop,
+            IncrementPointer,
+            ReadOrWrite,
+            DecrementPointer,
+            EndLoop,
+            WriteStdout,
+        ];
+        assert_eq!(
+            lint(&program),
+            [("infinite-loop", 1..6), ("unreachable-code", 6..7)]
+        );
+
+        // oom MOO moO Moo mOo moo OOM - the loop may not be entered
+        let mut program = program;
+        program[0] = ReadStdin;
+        assert_eq!(lint(&program), [("stuck-loop", 1..6)]);
+    }
+
+    #[test]
+    fn levels_works() {
+        let linter = Linter::default()
+            .level("dead-loop", Level::Deny)
+            .unwrap()
+            .level("dangling-register", Level::Allow)
+            .unwrap();
+        let diagnostics = linter.lint(&[CopyOrPaste, SetZero, BeginLoop, IncrementByte, EndLoop]);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].rule, DEAD_LOOP);
+        assert_eq!(diagnostics[0].level, Level::Deny);
+        assert!(Linter::default().level("moo", Level::Deny).is_err());
+    }
+}
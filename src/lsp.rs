@@ -0,0 +1,670 @@
+use std::{
+    collections::HashMap,
+    io::{BufRead, Write},
+    ops::Range,
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+
+use crate::{
+    dialect::{Command, Dialect},
+    errors::ErrorKind,
+    format::{self, Style},
+    instruction::Instruction,
+    lexer::Lexer,
+    message::{read_message, write_message},
+    syntax::{Kind, Node, SyntaxTree},
+    translate::{find_begin_loop, find_end_loop},
+};
+
+/// Error code of a request the server doesn't know.
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// Error code of a request which failed.
+const INTERNAL_ERROR: i64 = -32603;
+
+/// Semantic token types, indexed by [`token_type`].
+const TOKEN_TYPES: &[&str] = &["keyword", "operator", "variable", "function", "comment"];
+
+/// Diagnostic severities.
+const ERROR: i64 = 1;
+const WARNING: i64 = 2;
+
+/// Symbol kind of loops. There is no kind for blocks of code, and namespaces are the closest.
+const LOOP_SYMBOL: i64 = 3;
+
+/// A Language Server Protocol server for COW sources.
+///
+/// Documents are synchronized in full. Diagnostics come from the lexer warnings and from loops
+/// without a matching command, and going to the definition of `MOO` or `moo` goes to the command
+/// it jumps to.
+pub struct Server<W> {
+    /// Lexes the documents, with the options of the session.
+    lexer: Lexer,
+    /// Whether the dialect of each document is guessed from the extension of its URI instead of
+    /// taken from `lexer`.
+    guess_dialect: bool,
+    output: W,
+    /// Texts of the open documents, by URI.
+    documents: HashMap<String, String>,
+}
+
+impl<W: Write> Server<W> {
+    pub fn new(lexer: Lexer, output: W) -> Self {
+        Self {
+            lexer,
+            guess_dialect: false,
+            output,
+            documents: HashMap::new(),
+        }
+    }
+
+    /// Guesses the dialect of each document from the extension of its URI, see
+    /// [`Dialect::guess`].
+    pub fn guess_dialect(mut self, guess: bool) -> Self {
+        self.guess_dialect = guess;
+        self
+    }
+
+    /// Handles messages from `input` until the client exits.
+    pub fn serve<R: BufRead>(&mut self, input: &mut R) -> Result<()> {
+        while let Some(message) = read_message(input)? {
+            let method = message["method"].as_str().unwrap_or_default().to_string();
+            let params = message.get("params").cloned().unwrap_or(Value::Null);
+            log::debug!("LSP message `{method}`: {params}");
+
+            let Some(id) = message.get("id").cloned() else {
+                if method == "exit" {
+                    return Ok(());
+                }
+                self.notify(&method, params)?;
+                continue;
+            };
+            let mut response = json!({ "jsonrpc": "2.0", "id": id });
+            match self.handle(&method, &params) {
+                Some(Ok(result)) => response["result"] = result,
+                Some(Err(e)) => {
+                    response["error"] =
+                        json!({ "code": INTERNAL_ERROR, "message": format!("{e:#}") })
+                }
+                None => {
+                    response["error"] = json!({
+                        "code": METHOD_NOT_FOUND,
+                        "message": format!("Unsupported request `{method}`"),
+                    })
+                }
+            }
+            write_message(&mut self.output, &response)?;
+        }
+        Ok(())
+    }
+
+    /// Handles a notification from the client.
+    fn notify(&mut self, method: &str, params: Value) -> Result<()> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents.insert(uri.into(), text.into());
+                self.publish_diagnostics(uri)
+            }
+            "textDocument/didChange" => {
+                // The whole text is sent on each change, so only the last one matters.
+                let changes = params["contentChanges"].as_array();
+                if let Some(text) = changes.and_then(|changes| changes.last()) {
+                    let text = text["text"].as_str().unwrap_or_default();
+                    self.documents.insert(uri.into(), text.into());
+                }
+                self.publish_diagnostics(uri)
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                self.notification(
+                    "textDocument/publishDiagnostics",
+                    json!({ "uri": uri, "diagnostics": [] }),
+                )
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Handles a request, returning its result, or `None` if the request isn't supported.
+    fn handle(&mut self, method: &str, params: &Value) -> Option<Result<Value>> {
+        let result = match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                    "documentSymbolProvider": true,
+                    "documentFormattingProvider": true,
+                    "semanticTokensProvider": {
+                        "legend": { "tokenTypes": TOKEN_TYPES, "tokenModifiers": [] },
+                        "full": true,
+                    },
+                },
+                "serverInfo": { "name": "cowi", "version": env!("CARGO_PKG_VERSION") },
+            })),
+            "shutdown" => Ok(Value::Null),
+            "textDocument/hover" => self.hover(params),
+            "textDocument/definition" => self.definition(params),
+            "textDocument/documentSymbol" => self.symbols(params),
+            "textDocument/semanticTokens/full" => self.semantic_tokens(params),
+            "textDocument/formatting" => self.formatting(params),
+            _ => return None,
+        };
+        Some(result)
+    }
+
+    /// Shows what the instruction under the cursor does.
+    fn hover(&self, params: &Value) -> Result<Value> {
+        let document = self.document(params)?;
+        let offset = document.offset(&params["position"]);
+        let token = document
+            .tokens
+            .iter()
+            .find(|token| token.0.contains(&offset));
+        Ok(match token {
+            Some((range, Command::Cow(instruction))) => json!({
+                "contents": { "kind": "markdown", "value": describe(*instruction) },
+                "range": document.lines.range(range.clone()),
+            }),
+            _ => Value::Null,
+        })
+    }
+
+    /// Goes from a `MOO` to the `moo` it jumps after, and from a `moo` to the `MOO` it jumps to.
+    fn definition(&self, params: &Value) -> Result<Value> {
+        let document = self.document(params)?;
+        let offset = document.offset(&params["position"]);
+        let index = document
+            .tokens
+            .iter()
+            .position(|token| token.0.contains(&offset));
+        Ok(match index.and_then(|index| document.matching(index)) {
+            Some(matching) => json!({
+                "uri": params["textDocument"]["uri"],
+                "range": document.lines.range(document.tokens[matching].0.clone()),
+            }),
+            None => Value::Null,
+        })
+    }
+
+    /// Lists the loops, nested like `MOO` and `moo` are.
+    fn symbols(&self, params: &Value) -> Result<Value> {
+        let document = self.document(params)?;
+        Ok(document.symbols(&document.tree.nodes()).into())
+    }
+
+    fn semantic_tokens(&self, params: &Value) -> Result<Value> {
+        let document = self.document(params)?;
+        let mut data = vec![];
+        let (mut previous_line, mut previous_start) = (0, 0);
+        for element in document.tree.elements() {
+            let token_type = match element.kind {
+                Kind::Token(command) => token_type(command),
+                Kind::Comment => 4,
+                _ => continue,
+            };
+            // Tokens may not span several lines, but comments and tokens such as `Ook. Ook?`
+            // can.
+            for (line, start, length) in document.lines.segments(element.range.clone()) {
+                if line != previous_line {
+                    previous_start = 0;
+                }
+                data.extend([line - previous_line, start - previous_start, length]);
+                data.extend([token_type, 0]);
+                (previous_line, previous_start) = (line, start);
+            }
+        }
+        Ok(json!({ "data": data }))
+    }
+
+    /// Formats the whole document, indenting loops by the tab size of the editor.
+    fn formatting(&self, params: &Value) -> Result<Value> {
+        let document = self.document(params)?;
+        let style = Style {
+            indent: params["options"]["tabSize"].as_u64().unwrap_or(4) as usize,
+            ..Style::default()
+        };
+        let formatted = format::format(&document.lexer, &style)?;
+        Ok(if formatted == document.text {
+            json!([])
+        } else {
+            json!([{
+                "range": document.lines.range(0..document.text.len()),
+                "newText": formatted,
+            }])
+        })
+    }
+
+    fn publish_diagnostics(&mut self, uri: &str) -> Result<()> {
+        let Some(text) = self.documents.get(uri) else {
+            return Ok(());
+        };
+        let diagnostics = Document::new(&self.lexer(uri), text).diagnostics();
+        self.notification(
+            "textDocument/publishDiagnostics",
+            json!({ "uri": uri, "diagnostics": diagnostics }),
+        )
+    }
+
+    /// Analyzes the document of a request.
+    fn document(&self, params: &Value) -> Result<Document<'_>> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let text = self
+            .documents
+            .get(uri)
+            .with_context(|| format!("The document `{uri}` isn't open"))?;
+        Ok(Document::new(&self.lexer(uri), text))
+    }
+
+    /// Returns the lexer of the document at `uri`.
+    fn lexer(&self, uri: &str) -> Lexer {
+        let lexer = self.lexer.with_source(vec![]);
+        if self.guess_dialect {
+            lexer.dialect(Dialect::guess(Path::new(uri)))
+        } else {
+            lexer
+        }
+    }
+
+    fn notification(&mut self, method: &str, params: Value) -> Result<()> {
+        let message = json!({ "jsonrpc": "2.0", "method": method, "params": params });
+        write_message(&mut self.output, &message)
+    }
+}
+
+/// An open document, lexed.
+struct Document<'a> {
+    text: &'a str,
+    lexer: Lexer,
+    tree: SyntaxTree,
+    /// Byte ranges of the tokens in `text` and their commands.
+    tokens: Vec<(Range<usize>, Command)>,
+    lines: Lines<'a>,
+}
+
+impl<'a> Document<'a> {
+    fn new(lexer: &Lexer, text: &'a str) -> Self {
+        let lexer = lexer.with_source(text.into());
+        let tree = SyntaxTree::parse(&lexer);
+        let tokens = tree
+            .elements()
+            .iter()
+            .filter_map(|element| match element.kind {
+                Kind::Token(command) => Some((element.range.clone(), command)),
+                _ => None,
+            })
+            .collect();
+        Self {
+            text,
+            lexer,
+            tree,
+            tokens,
+            lines: Lines::new(text),
+        }
+    }
+
+    /// Returns the byte offset of an LSP position.
+    fn offset(&self, position: &Value) -> usize {
+        let line = position["line"].as_u64().unwrap_or_default() as usize;
+        let character = position["character"].as_u64().unwrap_or_default() as usize;
+        self.lines.offset(line, character)
+    }
+
+    /// Returns the index of the token matching the loop command at `index`, the way the
+    /// interpreter matches them for COW, or like brackets for dialects lowered from Brainfuck.
+    fn matching(&self, index: usize) -> Option<usize> {
+        let command = self.tokens[index].1;
+        let program: Option<Vec<_>> = self
+            .tokens
+            .iter()
+            .map(|(_, command)| match command {
+                Command::Cow(instruction) => Some(*instruction),
+                Command::Brainfuck(_) => None,
+            })
+            .collect();
+        if let Some(program) = program {
+            return match command {
+                Command::Cow(Instruction::BeginLoop) => find_end_loop(&program, index),
+                Command::Cow(Instruction::EndLoop) => find_begin_loop(&program, index),
+                _ => None,
+            };
+        }
+
+        let commands = self.tokens.iter().map(|(_, command)| command).enumerate();
+        let mut depth = 0;
+        if command.is_begin_loop() {
+            for (other, command) in commands.skip(index) {
+                depth += command.is_begin_loop() as i32 - command.is_end_loop() as i32;
+                if depth == 0 {
+                    return Some(other);
+                }
+            }
+        } else if command.is_end_loop() {
+            for (other, command) in commands.take(index + 1).rev() {
+                depth += command.is_end_loop() as i32 - command.is_begin_loop() as i32;
+                if depth == 0 {
+                    return Some(other);
+                }
+            }
+        }
+        None
+    }
+
+    fn diagnostics(&self) -> Vec<Value> {
+        let diagnostic = |range: Range<usize>, severity: i64, message: String| {
+            json!({
+                "range": self.lines.range(range),
+                "severity": severity,
+                "source": "cowi",
+                "message": message,
+            })
+        };
+
+        let bom = self.lexer.bom().unwrap_or_default().len();
+        let mut diagnostics = vec![];
+        for warning in self.lexer.warnings() {
+            // Warnings only have a position, so they cover the word there.
+            let shift = if warning.line == 1 { bom } else { 0 };
+            let start = self.lines.starts[warning.line - 1] + warning.column - 1 + shift;
+            let length = self.text.as_bytes()[start..]
+                .iter()
+                .take_while(|byte| !byte.is_ascii_whitespace())
+                .count();
+            diagnostics.push(diagnostic(start..start + length, WARNING, warning.message));
+        }
+        for (index, (range, command)) in self.tokens.iter().enumerate() {
+            let kind = if command.is_begin_loop() {
+                ErrorKind::UnmatchedEndLoop
+            } else if command.is_end_loop() {
+                ErrorKind::UnmatchedBeginLoop
+            } else {
+                continue;
+            };
+            if self.matching(index).is_none() {
+                diagnostics.push(diagnostic(range.clone(), ERROR, kind.to_string()));
+            }
+        }
+        diagnostics
+    }
+
+    fn symbols(&self, nodes: &[Node]) -> Vec<Value> {
+        nodes
+            .iter()
+            .filter_map(|node| {
+                let Node::Loop {
+                    begin,
+                    children,
+                    end,
+                } = node
+                else {
+                    return None;
+                };
+                let (line, _) = self.lines.position(begin.range.start);
+                let detail = match end {
+                    Some(_) => match count_tokens(children) {
+                        1 => "1 command".into(),
+                        count => format!("{count} commands"),
+                    },
+                    None => "never closed".into(),
+                };
+                let end = end.as_ref().map_or(self.text.len(), |end| end.range.end);
+                Some(json!({
+                    "name": format!("loop at line {}", line + 1),
+                    "detail": detail,
+                    "kind": LOOP_SYMBOL,
+                    "range": self.lines.range(begin.range.start..end),
+                    "selectionRange": self.lines.range(begin.range.clone()),
+                    "children": self.symbols(children),
+                }))
+            })
+            .collect()
+    }
+}
+
+/// Converts between byte offsets and LSP positions, whose characters are UTF-16 code units.
+struct Lines<'a> {
+    text: &'a str,
+    /// Byte offsets of the beginnings of the lines.
+    starts: Vec<usize>,
+}
+
+impl<'a> Lines<'a> {
+    fn new(text: &'a str) -> Self {
+        let starts = [0]
+            .into_iter()
+            .chain(text.match_indices('\n').map(|(offset, _)| offset + 1))
+            .collect();
+        Self { text, starts }
+    }
+
+    /// Returns the 0-based line and character of `offset`.
+    fn position(&self, offset: usize) -> (usize, usize) {
+        let line = self.starts.partition_point(|&start| start <= offset) - 1;
+        let character = self.text[self.starts[line]..offset].encode_utf16().count();
+        (line, character)
+    }
+
+    fn offset(&self, line: usize, character: usize) -> usize {
+        let Some(&start) = self.starts.get(line) else {
+            return self.text.len();
+        };
+        let mut units = 0;
+        for (offset, char) in self.text[start..].char_indices() {
+            if units >= character || char == '\n' {
+                return start + offset;
+            }
+            units += char.len_utf16();
+        }
+        self.text.len()
+    }
+
+    fn range(&self, range: Range<usize>) -> Value {
+        let (start_line, start_character) = self.position(range.start);
+        let (end_line, end_character) = self.position(range.end);
+        json!({
+            "start": { "line": start_line, "character": start_character },
+            "end": { "line": end_line, "character": end_character },
+        })
+    }
+
+    /// Splits `range` at line breaks, into the line, start character and length of each part.
+    fn segments(&self, range: Range<usize>) -> Vec<(usize, usize, usize)> {
+        let (first, _) = self.position(range.start);
+        let (last, _) = self.position(range.end);
+        (first..=last)
+            .filter_map(|line| {
+                let start = range.start.max(self.starts[line]);
+                let end = match self.starts.get(line + 1) {
+                    Some(&next) => range.end.min(next - 1),
+                    None => range.end,
+                };
+                let length = self.text.get(start..end)?.encode_utf16().count();
+                (length > 0).then(|| (line, self.position(start).1, length))
+            })
+            .collect()
+    }
+}
+
+/// Returns the hover text of `instruction`, linking to the full description of the commands.
+fn describe(instruction: Instruction) -> String {
+    format!(
+        "**`{instruction}`** (code {})\n\n{} See [COW](https://esolangs.org/wiki/COW).",
+        instruction.code(),
+        instruction.summary()
+    )
+}
+
+/// Returns the index of the semantic token type of `command` in [`TOKEN_TYPES`]: loops are
+/// keywords, arithmetic is operators, moves and the register are variables, and I/O and `mOO`
+/// are functions.
+fn token_type(command: Command) -> usize {
+    use Instruction::*;
+    match command {
+        Command::Cow(BeginLoop | EndLoop) | Command::Brainfuck(b'[' | b']') => 0,
+        Command::Cow(IncrementByte | DecrementByte | SetZero) | Command::Brainfuck(b'+' | b'-') => {
+            1
+        }
+        Command::Cow(IncrementPointer | DecrementPointer | CopyOrPaste)
+        | Command::Brainfuck(b'<' | b'>') => 2,
+        Command::Cow(ReadOrWrite | WriteStdout | ReadStdin | ExecuteValue)
+        | Command::Brainfuck(_) => 3,
+    }
+}
+
+fn count_tokens(nodes: &[Node]) -> usize {
+    nodes
+        .iter()
+        .map(|node| match node {
+            Node::Element(element) => !element.is_trivia() as usize,
+            Node::Loop { children, end, .. } => 1 + end.is_some() as usize + count_tokens(children),
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serve_works() {
+        let uri = "file:///loop.cow";
+        let text = "MoO MOO\n  MOo moo\nMOO";
+        let position = |line: usize, character: usize| {
+            json!({
+                "textDocument": { "uri": uri },
+                "position": { "line": line, "character": character },
+            })
+        };
+        let document = json!({ "textDocument": { "uri": uri } });
+
+        let mut input = vec![];
+        let messages = [
+            json!({ "id": 0, "method": "initialize", "params": {} }),
+            json!({ "method": "initialized", "params": {} }),
+            json!({
+                "method": "textDocument/didOpen",
+                "params": { "textDocument": { "uri": uri, "languageId": "cow", "text": text } },
+            }),
+            json!({ "id": 1, "method": "textDocument/hover", "params": position(1, 3) }),
+            json!({ "id": 2, "method": "textDocument/definition", "params": position(0, 5) }),
+            json!({ "id": 3, "method": "textDocument/documentSymbol", "params": document }),
+            json!({ "id": 4, "method": "textDocument/semanticTokens/full", "params": document }),
+            json!({
+                "id": 5,
+                "method": "textDocument/formatting",
+                "params": { "textDocument": { "uri": uri }, "options": { "tabSize": 2 } },
+            }),
+            json!({ "id": 6, "method": "textDocument/rename", "params": position(0, 0) }),
+            json!({ "id": 7, "method": "shutdown" }),
+            json!({ "method": "exit" }),
+            json!({ "id": 8, "method": "shutdown" }),
+        ];
+        for mut message in messages {
+            message["jsonrpc"] = "2.0".into();
+            write_message(&mut input, &message).unwrap();
+        }
+
+        let mut output = vec![];
+        let mut server = Server::new(Lexer::from_bytes(vec![]), &mut output);
+        server.serve(&mut &input[..]).unwrap();
+
+        let mut output = &output[..];
+        let mut messages = vec![];
+        while let Some(message) = read_message(&mut output).unwrap() {
+            messages.push(message);
+        }
+        let response = |id: i64| {
+            messages
+                .iter()
+                .find(|message| message["id"] == id)
+                .cloned()
+                .unwrap()
+        };
+        let range = |start: (usize, usize), end: (usize, usize)| {
+            json!({
+                "start": { "line": start.0, "character": start.1 },
+                "end": { "line": end.0, "character": end.1 },
+            })
+        };
+
+        let diagnostics = &messages[1]["params"]["diagnostics"];
+        assert_eq!(messages[1]["method"], "textDocument/publishDiagnostics");
+        assert_eq!(diagnostics.as_array().unwrap().len(), 1);
+        assert_eq!(diagnostics[0]["range"], range((2, 0), (2, 3)));
+        assert_eq!(diagnostics[0]["severity"], ERROR);
+
+        let hover = &response(1)["result"];
+        assert!(hover["contents"]["value"]
+            .as_str()
+            .unwrap()
+            .starts_with("**`MOo`** (code 5)\n\nDecrements the current block."));
+        assert!(hover["contents"]["value"]
+            .as_str()
+            .unwrap()
+            .ends_with("(https://esolangs.org/wiki/COW)."));
+        assert_eq!(hover["range"], range((1, 2), (1, 5)));
+
+        assert_eq!(response(2)["result"]["range"], range((1, 6), (1, 9)));
+
+        let symbols = &response(3)["result"];
+        assert_eq!(symbols.as_array().unwrap().len(), 2);
+        assert_eq!(symbols[0]["detail"], "1 command");
+        assert_eq!(symbols[0]["range"], range((0, 4), (1, 9)));
+        assert_eq!(symbols[1]["detail"], "never closed");
+
+        assert_eq!(
+            response(4)["result"]["data"],
+            json!([0, 0, 3, 1, 0, 0, 4, 3, 0, 0, 1, 2, 3, 1, 0, 0, 4, 3, 0, 0, 1, 0, 3, 0, 0])
+        );
+
+        let edits = &response(5)["result"];
+        assert_eq!(edits[0]["range"], range((0, 0), (2, 3)));
+        assert_eq!(edits[0]["newText"], "MoO MOO\n  MOo\nmoo\nMOO\n");
+
+        assert_eq!(response(6)["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(response(7)["result"], Value::Null);
+        // Nothing is read after `exit`.
+        assert!(messages.iter().all(|message| message["id"] != 8));
+    }
+
+    #[test]
+    fn dialect_is_guessed_per_document() {
+        let mut input = vec![];
+        for (uri, text) in [("file:///loop.b", "+["), ("file:///loop.cow", "+[")] {
+            let message = json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didOpen",
+                "params": { "textDocument": { "uri": uri, "languageId": "cow", "text": text } },
+            });
+            write_message(&mut input, &message).unwrap();
+        }
+
+        let mut output = vec![];
+        let mut server = Server::new(Lexer::from_bytes(vec![]), &mut output).guess_dialect(true);
+        server.serve(&mut &input[..]).unwrap();
+
+        let mut output = &output[..];
+        let brainfuck = read_message(&mut output).unwrap().unwrap();
+        assert_eq!(
+            brainfuck["params"]["diagnostics"].as_array().unwrap().len(),
+            1
+        );
+        let cow = read_message(&mut output).unwrap().unwrap();
+        assert_eq!(cow["params"]["diagnostics"], json!([]));
+    }
+
+    #[test]
+    fn lines_count_utf16() {
+        let lines = Lines::new("é😀\nMoO");
+        assert_eq!(lines.position(6), (0, 3));
+        assert_eq!(lines.offset(0, 3), 6);
+        assert_eq!(lines.offset(0, 10), 6);
+        assert_eq!(lines.offset(5, 0), 10);
+        assert_eq!(lines.segments(0..10), vec![(0, 0, 3), (1, 0, 3)]);
+    }
+}
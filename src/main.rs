@@ -1,19 +1,313 @@
-use std::path::PathBuf;
+use std::{
+    fs::File,
+    io::{BufRead, BufWriter, Read, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{atomic::AtomicBool, Arc, Mutex},
+};
 
-use clap::Parser;
+use anyhow::Context;
+use clap::{ArgEnum, Parser, Subcommand};
+use signal_hook::consts::{SIGINT, SIGTERM};
 
-use cowi::{interpreter::Interpreter, lexer::Lexer};
+use cowi::{
+    coverage::Coverage,
+    dap,
+    debugger::Debugger,
+    dialect::Dialect,
+    format::{self, Style},
+    instruction::Instruction,
+    interpreter::Interpreter,
+    lexer::Lexer,
+    lint::{Level, Linter},
+    lsp,
+    profile::Profiler,
+    repl::{Repl, Status},
+    snapshot::{Checkpointer, Snapshot, SourceOptions},
+    trace::{self, Filter, Tracer},
+    translate,
+    tui::Tui,
+    view,
+};
 
 #[derive(Parser)]
 #[clap(about, version, author, long_about = None)]
+#[clap(args_conflicts_with_subcommands = true, arg_required_else_help = true)]
 struct Args {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     /// Path to COW file
     #[clap(parse(from_os_str))]
-    file_path: PathBuf,
+    file_path: Option<PathBuf>,
 
     /// Specify log filter level
-    #[clap(short, long = "log-level", value_parser)]
+    #[clap(short, long = "log-level", value_parser, global = true)]
     log_level: Option<String>,
+
+    #[clap(flatten)]
+    lex_options: LexOptions,
+
+    #[clap(flatten)]
+    run_options: RunOptions,
+}
+
+#[derive(clap::Args)]
+struct LexOptions {
+    /// Language of the source: `cow`, `brainfuck`, `ook` or a path to a TOML token table.
+    /// Guessed from the file extension by default
+    #[clap(short, long, value_parser, global = true)]
+    dialect: Option<String>,
+
+    /// Ignore tokens glued to other letters, such as `moo` in `moon` or runs like `MoOMoO`
+    #[clap(long, global = true)]
+    strict: bool,
+
+    /// Ignore everything between `[[` and `]]`
+    #[clap(long, global = true)]
+    comments: bool,
+}
+
+impl LexOptions {
+    /// Returns the options to save in a checkpoint, with the path to a token table made
+    /// absolute so that it's found again on resume.
+    fn source_options(&self) -> SourceOptions {
+        let dialect = self.dialect.as_ref().map(|name| {
+            match Dialect::builtin(name)
+                .is_none()
+                .then(|| Path::new(name).canonicalize())
+            {
+                Some(Ok(path)) => path.to_string_lossy().into_owned(),
+                _ => name.clone(),
+            }
+        });
+        SourceOptions {
+            dialect,
+            strict: self.strict,
+            comment: self.comments.then(|| ("[[".to_string(), "]]".to_string())),
+        }
+    }
+}
+
+#[derive(clap::Args)]
+struct RunOptions {
+    /// Stop the program when it comes back to a loop in the exact same state, as it would loop
+    /// forever
+    #[clap(long)]
+    detect_loops: bool,
+
+    /// Count the executions of every instruction and loop, and print the hot spots to STDERR
+    /// when the program ends
+    #[clap(long)]
+    profile: bool,
+
+    /// Write the profile as JSON to FILE. Implies `--profile`
+    #[clap(long, value_name = "FILE", parse(from_os_str))]
+    profile_json: Option<PathBuf>,
+
+    /// Write the steps run in each loop nest to FILE as folded stacks, for flamegraph tools.
+    /// Implies `--profile`
+    #[clap(long, value_name = "FILE", parse(from_os_str))]
+    flamegraph: Option<PathBuf>,
+
+    /// Record which instructions run and which way each `MOO` goes, adding them to the coverage
+    /// data in FILE. See `cowi coverage`
+    #[clap(long, value_name = "FILE", parse(from_os_str))]
+    coverage: Option<PathBuf>,
+
+    /// Write a JSON line per instruction run to FILE, with the pointer, the current memory block
+    /// before and after it, the register and the byte read or written
+    #[clap(long, value_name = "FILE", parse(from_os_str))]
+    trace: Option<PathBuf>,
+
+    /// Only trace the instructions at these indices, e.g. `10..20`, `10..=20`, `10..` or `10`
+    #[clap(long, value_name = "RANGE", value_parser = trace::parse_range, requires = "trace")]
+    trace_pc: Option<Range<usize>>,
+
+    /// Only trace these instructions, e.g. `Moo,OOM`
+    #[clap(
+        long,
+        value_name = "LIST",
+        value_parser,
+        use_value_delimiter = true,
+        requires = "trace"
+    )]
+    trace_instruction: Vec<Instruction>,
+
+    /// Save the state of the machine to FILE every `--checkpoint-interval` steps and when the
+    /// program is interrupted, to go on later with `cowi resume FILE`. If the program fails, its
+    /// state is saved to FILE.failed instead
+    #[clap(long, value_name = "FILE", parse(from_os_str))]
+    checkpoint: Option<PathBuf>,
+
+    /// Number of steps between checkpoints
+    #[clap(long, value_name = "N", value_parser, default_value_t = 100_000_000)]
+    checkpoint_interval: u64,
+
+    /// Print the memory blocks in use to STDERR when the program ends, or only when it fails or
+    /// is interrupted with `--dump-memory=error`
+    #[clap(
+        long,
+        arg_enum,
+        value_name = "WHEN",
+        min_values = 0,
+        max_values = 1,
+        require_equals = true,
+        default_missing_value = "exit"
+    )]
+    dump_memory: Option<DumpWhen>,
+
+    /// Also write the memory dump as JSON to FILE
+    #[clap(
+        long,
+        value_name = "FILE",
+        parse(from_os_str),
+        requires = "dump-memory"
+    )]
+    dump_json: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run a COW program
+    Run {
+        #[clap(flatten)]
+        options: RunOptions,
+
+        /// Path to COW file
+        #[clap(parse(from_os_str))]
+        file_path: PathBuf,
+    },
+    /// Go on with a program from a state saved by `cowi run --checkpoint`
+    ///
+    /// The source is read again with the dialect, `--strict` and `--comments` it was run with. The
+    /// input read before the checkpoint is skipped, so give the same input again.
+    Resume {
+        #[clap(flatten)]
+        options: RunOptions,
+
+        /// Path to the state file
+        #[clap(parse(from_os_str))]
+        state_path: PathBuf,
+    },
+    /// Translate a program into COW or Brainfuck, printing the result to STDOUT
+    Translate {
+        /// Language to translate into
+        #[clap(long, arg_enum, default_value = "brainfuck")]
+        to: Language,
+
+        /// Path to the source file
+        #[clap(parse(from_os_str))]
+        file_path: PathBuf,
+    },
+    /// Check a program for common mistakes
+    Lint {
+        /// Don't report the rule with this ID
+        #[clap(short = 'A', long, value_name = "RULE", multiple_occurrences = true)]
+        allow: Vec<String>,
+
+        /// Report the rule with this ID as a warning
+        #[clap(short = 'W', long, value_name = "RULE", multiple_occurrences = true)]
+        warn: Vec<String>,
+
+        /// Report the rule with this ID as an error
+        #[clap(short = 'D', long, value_name = "RULE", multiple_occurrences = true)]
+        deny: Vec<String>,
+
+        /// Path to the source file
+        #[clap(parse(from_os_str))]
+        file_path: PathBuf,
+    },
+    /// Format COW sources in place
+    Fmt {
+        /// Maximum number of tokens on a line
+        #[clap(long, value_parser, default_value_t = 10)]
+        tokens_per_line: usize,
+
+        /// Number of spaces per loop nesting level
+        #[clap(long, value_parser, default_value_t = 4)]
+        indent: usize,
+
+        /// Put runs of at least MIN identical tokens, 4 unless given as `--fold=MIN`, on their
+        /// own lines with a `[[ MoO ×13 ]]` comment. Needs `--comments`
+        #[clap(
+            long,
+            value_parser,
+            value_name = "MIN",
+            min_values = 0,
+            max_values = 1,
+            require_equals = true,
+            default_missing_value = "4"
+        )]
+        fold: Option<usize>,
+
+        /// Don't write anything, but exit with an error if a file isn't formatted
+        #[clap(long)]
+        check: bool,
+
+        /// Paths to the source files
+        #[clap(parse(from_os_str), required = true)]
+        file_paths: Vec<PathBuf>,
+    },
+    /// Report the coverage recorded by `cowi run --coverage`, printing it to STDOUT
+    Coverage {
+        /// Format of the report
+        #[clap(long, arg_enum, default_value = "text")]
+        format: CoverageFormat,
+
+        /// Path to the source file
+        #[clap(parse(from_os_str))]
+        file_path: PathBuf,
+
+        /// Paths to the coverage data files, which are merged
+        #[clap(parse(from_os_str), required = true)]
+        data_paths: Vec<PathBuf>,
+    },
+    /// Run lines of COW code interactively, keeping the memory between them
+    Repl,
+    /// Run a program step by step, with breakpoints and watchpoints
+    Debug {
+        /// Path to the source file
+        #[clap(parse(from_os_str))]
+        file_path: PathBuf,
+    },
+    /// Show a program running in the terminal, with its memory, register and output
+    Tui {
+        /// Number of steps per second at start
+        #[clap(long, value_parser, default_value_t = 8.0)]
+        speed: f64,
+
+        /// Start paused, to go on step by step
+        #[clap(long)]
+        paused: bool,
+
+        /// Path to the source file
+        #[clap(parse(from_os_str))]
+        file_path: PathBuf,
+    },
+    /// Serve the Debug Adapter Protocol over STDIN and STDOUT, for editors
+    Dap,
+    /// Serve the Language Server Protocol over STDIN and STDOUT, for editors
+    Lsp,
+}
+
+#[derive(Clone, Copy, ArgEnum)]
+enum Language {
+    Cow,
+    Brainfuck,
+}
+
+#[derive(Clone, Copy, PartialEq, ArgEnum)]
+enum DumpWhen {
+    Exit,
+    Error,
+}
+
+#[derive(Clone, Copy, ArgEnum)]
+enum CoverageFormat {
+    Text,
+    Lcov,
+    Html,
 }
 
 fn main() -> anyhow::Result<()> {
@@ -24,15 +318,497 @@ fn main() -> anyhow::Result<()> {
     }
     env_logger::init();
 
-    let lexer = Lexer::new(arg.file_path)?;
-    let program = lexer.lex()?;
-    let interpreter = Interpreter::new(program);
+    match (arg.command, arg.file_path) {
+        (Some(Command::Run { options, file_path }), _) => {
+            run(file_path, &options, &arg.lex_options.source_options(), None)
+        }
+        (None, Some(file_path)) => run(
+            file_path,
+            &arg.run_options,
+            &arg.lex_options.source_options(),
+            None,
+        ),
+        (
+            Some(Command::Resume {
+                options,
+                state_path,
+            }),
+            _,
+        ) => {
+            let snapshot = Snapshot::load(&state_path)?;
+            let file_path = snapshot.source_path.clone();
+            let source_options = snapshot.source_options.clone();
+            run(file_path, &options, &source_options, Some(snapshot))
+        }
+        (Some(Command::Translate { to, file_path }), _) => {
+            translate(to, file_path, &arg.lex_options)
+        }
+        (
+            Some(Command::Fmt {
+                tokens_per_line,
+                indent,
+                fold,
+                check,
+                file_paths,
+            }),
+            _,
+        ) => {
+            let style = Style {
+                tokens_per_line,
+                indent,
+                fold,
+            };
+            fmt(&style, check, file_paths, &arg.lex_options)
+        }
+        (
+            Some(Command::Lint {
+                allow,
+                warn,
+                deny,
+                file_path,
+            }),
+            _,
+        ) => {
+            let mut linter = Linter::default();
+            for (ids, level) in [
+                (allow, Level::Allow),
+                (warn, Level::Warn),
+                (deny, Level::Deny),
+            ] {
+                for id in ids {
+                    linter = linter.level(&id, level)?;
+                }
+            }
+            lint(&linter, file_path, &arg.lex_options)
+        }
+        (
+            Some(Command::Coverage {
+                format,
+                file_path,
+                data_paths,
+            }),
+            _,
+        ) => coverage(format, file_path, &data_paths, &arg.lex_options),
+        (Some(Command::Repl), _) => {
+            let lexer = configure(Lexer::from_bytes(vec![]), Path::new(""), &arg.lex_options)?;
+            repl(Repl::new(lexer))
+        }
+        (Some(Command::Debug { file_path }), _) => {
+            let lexer = lexer(&file_path, &arg.lex_options)?;
+            let positions = lexer.instruction_positions();
+            let program = lex_with(lexer, &file_path)?;
+            debug(Debugger::new(program, positions))
+        }
+        (
+            Some(Command::Tui {
+                speed,
+                paused,
+                file_path,
+            }),
+            _,
+        ) => {
+            let lexer = lexer(&file_path, &arg.lex_options)?;
+            let positions = lexer.instruction_positions();
+            let lengths = lexer.instruction_lengths().unwrap_or_default();
+            let source = lexer.source().to_vec();
+            let program = lex_with(lexer, &file_path)?;
+            let mut tui = Tui::new(program, &source, positions)
+                .lengths(lengths)
+                .speed(speed)
+                .paused(paused);
+            let mut terminal = ratatui::init();
+            let result = tui.run(&mut terminal);
+            ratatui::restore();
+            result
+        }
+        (Some(Command::Dap), _) => {
+            let lexer = configure(Lexer::from_bytes(vec![]), Path::new(""), &arg.lex_options)?;
+            let mut server = dap::Server::new(lexer, std::io::stdout().lock())
+                .guess_dialect(arg.lex_options.dialect.is_none());
+            server.serve(std::io::BufReader::new(std::io::stdin()))
+        }
+        (Some(Command::Lsp), _) => {
+            let lexer = configure(Lexer::from_bytes(vec![]), Path::new(""), &arg.lex_options)?;
+            let mut server = lsp::Server::new(lexer, std::io::stdout().lock())
+                .guess_dialect(arg.lex_options.dialect.is_none());
+            server.serve(&mut std::io::stdin().lock())
+        }
+        (None, None) => unreachable!("clap prints help when no arguments are given"),
+    }
+}
+
+fn dialect(name: Option<&str>, file_path: &Path) -> anyhow::Result<Dialect> {
+    let dialect = match name {
+        Some(name) => match Dialect::builtin(name) {
+            Some(dialect) => dialect,
+            None if Path::new(name).is_file() => Dialect::load(Path::new(name))?,
+            None => anyhow::bail!("Unknown dialect `{name}`"),
+        },
+        None => Dialect::guess(file_path),
+    };
+    log::info!("Reading the source as `{}`", dialect.name());
+    Ok(dialect)
+}
+
+fn lexer(file_path: &Path, options: &LexOptions) -> anyhow::Result<Lexer> {
+    configure(Lexer::new(file_path.to_path_buf())?, file_path, options)
+}
+
+/// Applies `options` to `lexer`, guessing the dialect from `file_path`.
+fn configure(lexer: Lexer, file_path: &Path, options: &LexOptions) -> anyhow::Result<Lexer> {
+    apply(lexer, file_path, &options.source_options())
+}
+
+fn apply(lexer: Lexer, file_path: &Path, options: &SourceOptions) -> anyhow::Result<Lexer> {
+    let dialect = dialect(options.dialect.as_deref(), file_path)?;
+    let mut lexer = lexer.dialect(dialect).strict(options.strict);
+    if let Some((start, end)) = &options.comment {
+        lexer = lexer.comment(start, end);
+    }
+    Ok(lexer)
+}
+
+fn lex(file_path: PathBuf, options: &LexOptions) -> anyhow::Result<Vec<Instruction>> {
+    lex_with(lexer(&file_path, options)?, &file_path)
+}
+
+/// Lexes with `lexer`, printing its warnings.
+fn lex_with(lexer: Lexer, file_path: &Path) -> anyhow::Result<Vec<Instruction>> {
+    for warning in lexer.warnings() {
+        eprintln!("warning: {}:{warning}", file_path.display());
+    }
+    Ok(lexer.lex()?)
+}
+
+/// Runs the program in `file_path`, from `snapshot` if it's given.
+fn run(
+    file_path: PathBuf,
+    options: &RunOptions,
+    source_options: &SourceOptions,
+    snapshot: Option<Snapshot>,
+) -> anyhow::Result<()> {
+    let lexer = apply(Lexer::new(file_path.clone())?, &file_path, source_options)?;
+    let positions = lexer.instruction_positions();
+    let source = lexer.source().to_vec();
+    let program = lex_with(lexer, &file_path)?;
+    let profile = options.profile || options.profile_json.is_some() || options.flamegraph.is_some();
+    let profiler = profile.then(|| Arc::new(Mutex::new(Profiler::new(&program))));
+    let coverage = options
+        .coverage
+        .is_some()
+        .then(|| Arc::new(Mutex::new(Coverage::new(&program))));
+    let mut interpreter = Interpreter::new(program).detect_repeats(options.detect_loops);
+    if let Some(profiler) = &profiler {
+        interpreter = interpreter.observe(profiler.clone());
+    }
+    if let Some(coverage) = &coverage {
+        interpreter = interpreter.observe(coverage.clone());
+    }
+    if options.dump_memory.is_some() || options.checkpoint.is_some() {
+        let interrupted = Arc::new(AtomicBool::new(false));
+        for signal in [SIGINT, SIGTERM] {
+            // A second signal kills the program, e.g. if it's stuck reading input.
+            signal_hook::flag::register_conditional_shutdown(signal, 1, interrupted.clone())?;
+            signal_hook::flag::register(signal, interrupted.clone())?;
+        }
+        interpreter = interpreter.interrupt_on(interrupted);
+    }
+    let mut stdin = std::io::stdin().lock();
+    if let Some(snapshot) = &snapshot {
+        interpreter
+            .restore(snapshot)
+            .with_context(|| format!("Failed to resume `{}`", file_path.display()))?;
+        let position = snapshot.input_position();
+        let skipped = std::io::copy(&mut (&mut stdin).take(position), &mut std::io::sink())?;
+        anyhow::ensure!(
+            skipped == position,
+            "The input ends before the {position} bytes read before the checkpoint"
+        );
+    }
+    let checkpointer = match &options.checkpoint {
+        Some(path) => {
+            let source_path = file_path
+                .canonicalize()
+                .with_context(|| format!("Failed to find `{}`", file_path.display()))?;
+            let checkpointer =
+                Checkpointer::new(path.clone(), source_path, options.checkpoint_interval)
+                    .source_options(source_options.clone());
+            let checkpointer = Arc::new(Mutex::new(checkpointer));
+            interpreter = interpreter.observe(checkpointer.clone());
+            Some(checkpointer)
+        }
+        None => None,
+    };
+    let tracer = match &options.trace {
+        Some(path) => {
+            let file = File::create(path)
+                .with_context(|| format!("Failed to create `{}`", path.display()))?;
+            let filter = Filter {
+                program_counters: options.trace_pc.clone(),
+                instructions: (!options.trace_instruction.is_empty())
+                    .then(|| options.trace_instruction.clone()),
+            };
+            let tracer = Arc::new(Mutex::new(Tracer::new(BufWriter::new(file), filter)));
+            interpreter = interpreter.observe(tracer.clone());
+            Some(tracer)
+        }
+        None => None,
+    };
 
-    if let Err(e) = interpreter.run() {
+    let mut stdout = std::io::stdout().lock();
+    let result = interpreter.run_with(&mut stdin, &mut stdout);
+    if let Err(e) = &result {
         log::error!("{e}.");
     } else {
         log::info!("Done.\n")
     }
+    stdout.flush()?;
+    if options.dump_memory == Some(DumpWhen::Exit)
+        || options.dump_memory.is_some() && result.is_err()
+    {
+        eprint!("\n{}", view::dump(&interpreter));
+        if let Some(path) = &options.dump_json {
+            let json = view::dump_json(&interpreter);
+            std::fs::write(path, serde_json::to_string_pretty(&json)?)
+                .with_context(|| format!("Failed to write `{}`", path.display()))?;
+        }
+    }
+    if let Some(tracer) = tracer {
+        tracer.lock().unwrap().finish()?;
+    }
+    if let Some(checkpointer) = checkpointer {
+        checkpointer.lock().unwrap().finish()?;
+    }
+
+    if let (Some(coverage), Some(path)) = (coverage, &options.coverage) {
+        let mut coverage = coverage.lock().unwrap().clone();
+        if path.exists() {
+            coverage
+                .merge(&Coverage::load(path)?)
+                .with_context(|| format!("Failed to add to `{}`", path.display()))?;
+        }
+        coverage.save(path)?;
+    }
+    if let Some(profiler) = profiler {
+        let profiler = profiler.lock().unwrap();
+        eprint!("\n{}", profiler.report(positions.as_deref(), &source));
+        if let Some(path) = &options.profile_json {
+            let json = profiler.to_json(positions.as_deref());
+            std::fs::write(path, serde_json::to_string_pretty(&json)?)
+                .with_context(|| format!("Failed to write `{}`", path.display()))?;
+        }
+        if let Some(path) = &options.flamegraph {
+            let root = file_path.file_name().unwrap_or_default().to_string_lossy();
+            std::fs::write(path, profiler.folded(&root, positions.as_deref()))
+                .with_context(|| format!("Failed to write `{}`", path.display()))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn coverage(
+    format: CoverageFormat,
+    file_path: PathBuf,
+    data_paths: &[PathBuf],
+    options: &LexOptions,
+) -> anyhow::Result<()> {
+    let lexer = lexer(&file_path, options)?;
+    let positions = lexer
+        .instruction_positions()
+        .context("Coverage can only be reported for COW sources")?;
+    let source = lexer.source().to_vec();
+    let program = lex_with(lexer, &file_path)?;
+
+    let mut coverage: Option<Coverage> = None;
+    for path in data_paths {
+        let data = Coverage::load(path)?;
+        anyhow::ensure!(
+            data.is_of(&program),
+            "`{}` was recorded for another program",
+            path.display()
+        );
+        match &mut coverage {
+            Some(coverage) => coverage.merge(&data)?,
+            None => coverage = Some(data),
+        }
+    }
+    let coverage = coverage.expect("clap requires a data path");
+
+    let report = match format {
+        CoverageFormat::Text => coverage.text(&source, &positions),
+        CoverageFormat::Lcov => coverage.lcov(&file_path, &source, &positions),
+        CoverageFormat::Html => {
+            let title = file_path.display().to_string();
+            coverage.html(&title, &source, &positions)
+        }
+    };
+    print!("{report}");
+    Ok(())
+}
+
+fn translate(to: Language, file_path: PathBuf, options: &LexOptions) -> anyhow::Result<()> {
+    let program = lex(file_path, options)?;
+    match to {
+        Language::Cow => println!("{}", translate::to_source(&program)),
+        Language::Brainfuck => println!("{}", translate::cow_to_brainfuck(&program)?),
+    }
+    Ok(())
+}
+
+fn fmt(
+    style: &Style,
+    check: bool,
+    file_paths: Vec<PathBuf>,
+    options: &LexOptions,
+) -> anyhow::Result<()> {
+    let mut unformatted = 0;
+    for file_path in file_paths {
+        let lexer = lexer(&file_path, options)?;
+        let formatted = format::format(&lexer, style)
+            .with_context(|| format!("Failed to format `{}`", file_path.display()))?;
+        let bom = lexer.bom().unwrap_or_default();
+        if formatted.as_bytes() == [bom, lexer.source()].concat() {
+            continue;
+        }
+        if check {
+            println!("`{}` is not formatted", file_path.display());
+            unformatted += 1;
+        } else {
+            std::fs::write(&file_path, formatted)
+                .with_context(|| format!("Failed to write `{}`", file_path.display()))?;
+        }
+    }
+    if unformatted > 0 {
+        anyhow::bail!("{unformatted} file(s) not formatted");
+    }
+    Ok(())
+}
 
+fn lint(linter: &Linter, file_path: PathBuf, options: &LexOptions) -> anyhow::Result<()> {
+    let lexer = lexer(&file_path, options)?;
+    let positions = lexer.instruction_positions();
+    let program = lex_with(lexer, &file_path)?;
+
+    let diagnostics = linter.lint(&program);
+    for diagnostic in &diagnostics {
+        let index = diagnostic.range.start;
+        let location = match positions
+            .as_ref()
+            .and_then(|positions| positions.get(index))
+        {
+            Some((line, column)) => format!("{}:{line}:{column}", file_path.display()),
+            None => format!("{} (instruction {index})", file_path.display()),
+        };
+        eprintln!(
+            "{}[{}]: {location}: {}",
+            diagnostic.level.as_str(),
+            diagnostic.rule.id,
+            diagnostic.message
+        );
+    }
+    let errors = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.level == Level::Deny)
+        .count();
+    if errors > 0 {
+        anyhow::bail!("{errors} lint error(s)");
+    }
     Ok(())
 }
+
+fn repl(mut repl: Repl) -> anyhow::Result<()> {
+    let mut stdin = std::io::stdin().lock();
+    let mut stdout = std::io::stdout().lock();
+    writeln!(stdout, "Type `:help` for help, `:quit` or Ctrl-D to leave.")?;
+    let mut status = Status::Ready;
+    loop {
+        let prompt = match status {
+            Status::Continue => "... ",
+            _ => "cow> ",
+        };
+        write!(stdout, "{prompt}")?;
+        stdout.flush()?;
+
+        let mut line = String::new();
+        if stdin.read_line(&mut line)? == 0 {
+            writeln!(stdout)?;
+            return Ok(());
+        }
+        status = match repl.eval(&line, &mut stdin, &mut stdout) {
+            Ok(Status::Quit) => return Ok(()),
+            Ok(status) => status,
+            Err(e) => {
+                eprintln!("error: {e}");
+                Status::Ready
+            }
+        };
+    }
+}
+
+fn debug(mut debugger: Debugger) -> anyhow::Result<()> {
+    let mut stdin = std::io::stdin().lock();
+    let mut stdout = std::io::stdout().lock();
+    writeln!(
+        stdout,
+        "Stopped at {}. Type `help` for help, `quit` or Ctrl-D to leave.",
+        debugger.describe(0)
+    )?;
+    loop {
+        write!(stdout, "(cowi) ")?;
+        stdout.flush()?;
+
+        let mut line = String::new();
+        if stdin.read_line(&mut line)? == 0 {
+            writeln!(stdout)?;
+            return Ok(());
+        }
+        match debugger.eval(&line, &mut stdin, &mut stdout) {
+            Ok(true) => {}
+            Ok(false) => return Ok(()),
+            Err(e) => eprintln!("error: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dump_memory_takes_no_value_unless_glued() {
+        let args = Args::try_parse_from(["cowi", "--dump-memory", "prog.cow"]).unwrap();
+        assert_eq!(args.file_path, Some(PathBuf::from("prog.cow")));
+        assert!(args.run_options.dump_memory == Some(DumpWhen::Exit));
+
+        let args = Args::try_parse_from(["cowi", "run", "--dump-memory", "prog.cow"]).unwrap();
+        let Some(Command::Run { options, file_path }) = args.command else {
+            panic!("`run` wasn't parsed");
+        };
+        assert_eq!(file_path, PathBuf::from("prog.cow"));
+        assert!(options.dump_memory == Some(DumpWhen::Exit));
+
+        let args = Args::try_parse_from(["cowi", "--dump-memory=error", "prog.cow"]).unwrap();
+        assert!(args.run_options.dump_memory == Some(DumpWhen::Error));
+    }
+
+    #[test]
+    fn fold_takes_no_value_unless_glued() {
+        let fold = |args: &[&str]| match Args::try_parse_from(args).unwrap().command {
+            Some(Command::Fmt {
+                fold, file_paths, ..
+            }) => (fold, file_paths),
+            _ => panic!("`fmt` wasn't parsed"),
+        };
+        let paths = vec![PathBuf::from("prog.cow")];
+        assert_eq!(
+            fold(&["cowi", "fmt", "--fold", "prog.cow"]),
+            (Some(4), paths.clone())
+        );
+        assert_eq!(
+            fold(&["cowi", "fmt", "--fold=8", "prog.cow"]),
+            (Some(8), paths.clone())
+        );
+        assert_eq!(fold(&["cowi", "fmt", "prog.cow"]), (None, paths));
+    }
+}
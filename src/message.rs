@@ -0,0 +1,64 @@
+use std::io::{BufRead, Write};
+
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+
+/// Reads a JSON message framed with a `Content-Length` header, as used by the Debug Adapter
+/// Protocol and the Language Server Protocol. Returns `None` at the end of `input`.
+pub fn read_message<R: BufRead>(input: &mut R) -> Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            if length.is_some() {
+                bail!("The input ended in the middle of a header");
+            }
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = Some(
+                    value
+                        .trim()
+                        .parse::<usize>()
+                        .with_context(|| format!("Invalid header `{line}`"))?,
+                );
+            }
+        }
+    }
+
+    let length = length.context("A message has no `Content-Length` header")?;
+    let mut content = vec![0; length];
+    input.read_exact(&mut content)?;
+    Ok(Some(serde_json::from_slice(&content)?))
+}
+
+/// Writes `message` with a `Content-Length` header.
+pub fn write_message<W: Write>(output: &mut W, message: &Value) -> Result<()> {
+    let content = serde_json::to_string(message)?;
+    write!(output, "Content-Length: {}\r\n\r\n{content}", content.len())?;
+    output.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_roundtrip() {
+        let message = serde_json::json!({ "command": "initialize", "seq": 1 });
+        let mut framed = vec![];
+        write_message(&mut framed, &message).unwrap();
+        write_message(&mut framed, &message).unwrap();
+
+        let mut input = &framed[..];
+        assert_eq!(read_message(&mut input).unwrap(), Some(message.clone()));
+        assert_eq!(read_message(&mut input).unwrap(), Some(message));
+        assert_eq!(read_message(&mut input).unwrap(), None);
+    }
+}
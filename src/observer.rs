@@ -0,0 +1,130 @@
+use crate::interpreter::Interpreter;
+
+/// Input or output done by a step, reported to [`Observer::on_io`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Io {
+    /// `Moo` read a byte.
+    ReadByte(u8),
+    /// `oom` read an integer.
+    ReadInteger(i32),
+    /// `Moo` wrote a byte.
+    WriteByte(u8),
+    /// `OOM` wrote an integer.
+    WriteInteger(i32),
+}
+
+/// Sees the steps run by an [`Interpreter`] set up with [`Interpreter::observe`], for tools
+/// such as tracers, profilers and coverage. Every callback is given the interpreter, to read its state with
+/// [`Interpreter::program_counter`], [`Interpreter::pointer`], [`Interpreter::memory`] and
+/// [`Interpreter::register`]. Nothing needs to be implemented but the callbacks used.
+///
+/// A step calls [`Observer::before_step`], then [`Observer::on_io`] for its input or output,
+/// and ends with either [`Observer::on_jump`] and [`Observer::after_step`], or
+/// [`Observer::on_error`]. Steps undone with [`Interpreter::step_back`] aren't reported.
+/// [`Observer::on_stop`] tells when the interpreter stops running.
+pub trait Observer {
+    /// Called before the instruction at the program counter runs.
+    fn before_step(&mut self, _interpreter: &Interpreter) {}
+
+    /// Called after the instruction at `program_counter` ran, once the program counter is on the
+    /// next one.
+    fn after_step(&mut self, _interpreter: &Interpreter, _program_counter: usize) {}
+
+    /// Called when `MOO` or `moo` jumped, so that the next instruction is at `to` rather than
+    /// `from + 1`.
+    fn on_jump(&mut self, _interpreter: &Interpreter, _from: usize, _to: usize) {}
+
+    /// Called when the running instruction read or wrote something.
+    fn on_io(&mut self, _interpreter: &Interpreter, _io: Io) {}
+
+    /// Called instead of [`Observer::after_step`] when the instruction failed, including when
+    /// the run was interrupted. The program counter stays on it.
+    fn on_error(&mut self, _interpreter: &Interpreter, _error: &anyhow::Error) {}
+
+    /// Called when [`Interpreter::run_with`] returns, and after every [`Interpreter::step`], as
+    /// the time until the next one isn't the program's.
+    fn on_stop(&mut self, _interpreter: &Interpreter) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::{instruction::Instruction::*, interpreter::Event};
+
+    #[derive(Default)]
+    struct Recorder(Vec<String>);
+
+    impl Observer for Recorder {
+        fn before_step(&mut self, interpreter: &Interpreter) {
+            self.0
+                .push(format!("before {}", interpreter.program_counter()));
+        }
+
+        fn after_step(&mut self, interpreter: &Interpreter, program_counter: usize) {
+            let value = interpreter.memory()[interpreter.pointer()];
+            self.0.push(format!("after {program_counter} ({value})"));
+        }
+
+        fn on_jump(&mut self, _interpreter: &Interpreter, from: usize, to: usize) {
+            self.0.push(format!("jump {from} -> {to}"));
+        }
+
+        fn on_io(&mut self, _interpreter: &Interpreter, io: Io) {
+            self.0.push(format!("{io:?}"));
+        }
+
+        fn on_error(&mut self, _interpreter: &Interpreter, error: &anyhow::Error) {
+            self.0.push(format!("error {error}"));
+        }
+    }
+
+    #[test]
+    fn observer_works() {
+        // MoO MoO MOO MOo moo OOM Moo mOO
+        let program = vec![
+            IncrementByte,
+            IncrementByte,
+            BeginLoop,
+            DecrementByte,
+            EndLoop,
+            WriteStdout,
+            ReadOrWrite,
+            ExecuteValue,
+        ];
+        let recorder = Arc::new(Mutex::new(Recorder::default()));
+        let mut interpreter = Interpreter::new(program).observe(recorder.clone());
+        interpreter.push_input(b"\x03");
+        while !matches!(interpreter.step(), Event::Halted | Event::Error(_)) {}
+
+        assert_eq!(
+            recorder.lock().unwrap().0,
+            [
+                "before 0",
+                "after 0 (1)",
+                "before 1",
+                "after 1 (2)",
+                "before 2",
+                "after 2 (2)",
+                "before 3",
+                "after 3 (1)",
+                "before 4",
+                "jump 4 -> 3",
+                "after 4 (1)",
+                "before 3",
+                "after 3 (0)",
+                "before 4",
+                "after 4 (0)",
+                "before 5",
+                "WriteInteger(0)",
+                "after 5 (0)",
+                "before 6",
+                "ReadByte(3)",
+                "after 6 (3)",
+                "before 7",
+                "error Code 3 (`mOO`) can't execute itself as it would cause an infinite loop",
+            ]
+        );
+    }
+}
@@ -0,0 +1,421 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    ops::Range,
+    time::{Duration, Instant},
+};
+
+use serde_json::{json, Value};
+
+use crate::{
+    ast,
+    instruction::{AsInstruction, Instruction},
+    interpreter::Interpreter,
+    observer::Observer,
+};
+
+/// Number of rows of the instruction and loop tables of [`Profiler::report`].
+const TOP: usize = 10;
+
+/// Number of characters of a source line shown in a report before it's cut.
+const SOURCE_WIDTH: usize = 40;
+
+/// An [`Observer`] counting what a run executes.
+///
+/// The clock is only read when the innermost loop around the running instruction changes, so
+/// timing a loop nest costs little more than counting its instructions.
+#[derive(Debug, Clone)]
+pub struct Profiler {
+    program: Vec<Instruction>,
+    /// Executions of each instruction.
+    counts: Vec<u64>,
+    /// The well-nested loops, outer ones first.
+    loops: Vec<Range<usize>>,
+    /// Innermost loop of each instruction, as an index into `loops`.
+    innermost: Vec<Option<usize>>,
+    /// Time spent with each loop as the innermost one, then outside of any loop.
+    times: Vec<Duration>,
+    /// Innermost loop of the running instruction, and since when.
+    current: Option<usize>,
+    since: Option<Instant>,
+    /// `mOO` dispatches by code, including invalid ones.
+    dispatches: BTreeMap<i32, u64>,
+}
+
+/// What a [`Profiler`] found about a loop.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoopProfile {
+    /// Instructions from `MOO` to `moo` included.
+    pub range: Range<usize>,
+    /// Number of times `MOO` ran, whether the loop was entered or skipped.
+    pub entries: u64,
+    /// Number of times `moo` ran, which is the number of times the body ran through.
+    pub iterations: u64,
+    /// Number of steps in the loop, including the loops it contains.
+    pub steps: u64,
+    /// Time spent in the loop, including the loops it contains.
+    pub time: Duration,
+    /// Time spent in the loop outside of the loops it contains.
+    pub self_time: Duration,
+}
+
+impl Profiler {
+    pub fn new(program: &[Instruction]) -> Self {
+        let loops = ast::parse(program).loops(0);
+        let mut innermost = vec![None; program.len()];
+        // Inner loops come later and take over.
+        for (id, range) in loops.iter().enumerate() {
+            innermost[range.clone()].fill(Some(id));
+        }
+        Self {
+            program: program.to_vec(),
+            counts: vec![0; program.len()],
+            times: vec![Duration::ZERO; loops.len() + 1],
+            loops,
+            innermost,
+            current: None,
+            since: None,
+            dispatches: BTreeMap::new(),
+        }
+    }
+
+    /// Counts the instruction at `index`, about to run with `value` in the current memory block.
+    fn count(&mut self, index: usize, value: i32) {
+        self.counts[index] += 1;
+        if self.program[index] == Instruction::ExecuteValue {
+            *self.dispatches.entry(value).or_default() += 1;
+        }
+        let nest = self.innermost[index];
+        if nest != self.current || self.since.is_none() {
+            let now = Instant::now();
+            if let Some(since) = self.since {
+                self.times[self.current.unwrap_or(self.loops.len())] += now - since;
+            }
+            self.current = nest;
+            self.since = Some(now);
+        }
+    }
+
+    /// Stops the clock until the next instruction is counted.
+    fn stop(&mut self) {
+        if let Some(since) = self.since.take() {
+            self.times[self.current.unwrap_or(self.loops.len())] += since.elapsed();
+        }
+    }
+
+    /// Executions of each instruction, by index.
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    /// Total number of instructions run.
+    pub fn steps(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Total time measured.
+    pub fn time(&self) -> Duration {
+        self.times.iter().sum()
+    }
+
+    /// Number of `mOO` dispatches by code, including codes which aren't instructions.
+    pub fn dispatches(&self) -> &BTreeMap<i32, u64> {
+        &self.dispatches
+    }
+
+    /// Returns the profile of each well-nested loop, outer ones first.
+    pub fn loops(&self) -> Vec<LoopProfile> {
+        self.loops
+            .iter()
+            .enumerate()
+            .map(|(id, range)| {
+                // Loops inside this one follow it.
+                let nested = self.loops[id..]
+                    .iter()
+                    .take_while(|other| other.start < range.end)
+                    .count();
+                LoopProfile {
+                    range: range.clone(),
+                    entries: self.counts[range.start],
+                    iterations: self.counts[range.end - 1],
+                    steps: self.counts[range.clone()].iter().sum(),
+                    time: self.times[id..id + nested].iter().sum(),
+                    self_time: self.times[id],
+                }
+            })
+            .collect()
+    }
+
+    /// Renders the most executed instructions and loops, and the `mOO` dispatches, annotated
+    /// with their line in `source` when `positions` are known.
+    pub fn report(&self, positions: Option<&[(usize, usize)]>, source: &[u8]) -> String {
+        let steps = self.steps();
+        let percent = |count: u64| 100.0 * count as f64 / steps.max(1) as f64;
+        let lines: Vec<_> = source.split(|&byte| byte == b'\n').collect();
+        let locate = |index: usize| {
+            let position = positions.and_then(|positions| positions.get(index));
+            match position {
+                Some(&(line, column)) => {
+                    let full = String::from_utf8_lossy(lines.get(line - 1).unwrap_or(&&[][..]));
+                    let full = full.trim();
+                    let mut text: String = full.chars().take(SOURCE_WIDTH).collect();
+                    if text.len() < full.len() {
+                        text.push('…');
+                    }
+                    (format!("#{index} ({line}:{column})"), text)
+                }
+                None => (format!("#{index}"), String::new()),
+            }
+        };
+
+        let mut report = format!("{steps} steps in {:.3?}\n", self.time());
+
+        let mut instructions: Vec<_> = (0..self.counts.len())
+            .filter(|&index| self.counts[index] > 0)
+            .collect();
+        instructions.sort_by_key(|&index| std::cmp::Reverse(self.counts[index]));
+        if !instructions.is_empty() {
+            writeln!(report, "\nHot instructions:").unwrap();
+            writeln!(
+                report,
+                "{:>12} {:>7}  {:<20} source",
+                "count", "%", "instruction"
+            )
+            .unwrap();
+        }
+        for &index in instructions.iter().take(TOP) {
+            let count = self.counts[index];
+            let (location, text) = locate(index);
+            let location = format!("{location} {}", self.program[index]);
+            writeln!(
+                report,
+                "{count:>12} {:>6.1}%  {location:<20} {text}",
+                percent(count)
+            )
+            .unwrap();
+        }
+
+        let mut loops = self.loops();
+        loops.retain(|profile| profile.entries > 0);
+        loops.sort_by_key(|profile| std::cmp::Reverse(profile.steps));
+        if !loops.is_empty() {
+            writeln!(report, "\nHot loops:").unwrap();
+            writeln!(
+                report,
+                "{:>12} {:>7} {:>12} {:>12}  {:<20} source",
+                "steps", "%", "iterations", "time", "loop"
+            )
+            .unwrap();
+        }
+        for profile in loops.iter().take(TOP) {
+            let (location, text) = locate(profile.range.start);
+            writeln!(
+                report,
+                "{:>12} {:>6.1}% {:>12} {:>12}  {location:<20} {text}",
+                profile.steps,
+                percent(profile.steps),
+                profile.iterations,
+                format!("{:.3?}", profile.time),
+            )
+            .unwrap();
+        }
+
+        if !self.dispatches.is_empty() {
+            writeln!(report, "\nmOO dispatches:").unwrap();
+            writeln!(report, "{:>12}  code", "count").unwrap();
+        }
+        for (&code, &count) in &self.dispatches {
+            let name = match code.as_instruction() {
+                Some(Instruction::ExecuteValue) | None => "invalid",
+                Some(instruction) => instruction.as_str(),
+            };
+            writeln!(report, "{count:>12}  {code} {name}").unwrap();
+        }
+        report
+    }
+
+    /// Renders the steps run in each loop nest as folded stacks for flamegraph tools such as
+    /// inferno, e.g. `hello.cow;MOO 2:1;MOO 4:3 36`. The first frame is `root`, and each of the
+    /// others is a loop named after the position of its `MOO` when `positions` are known, or
+    /// its index otherwise. A stack counts the steps run with its last loop as the innermost one.
+    pub fn folded(&self, root: &str, positions: Option<&[(usize, usize)]>) -> String {
+        let mut steps = vec![0; self.loops.len() + 1];
+        for (index, count) in self.counts.iter().enumerate() {
+            steps[self.innermost[index].unwrap_or(self.loops.len())] += count;
+        }
+
+        let mut folded = String::new();
+        if steps[self.loops.len()] > 0 {
+            writeln!(folded, "{root} {}", steps[self.loops.len()]).unwrap();
+        }
+        // The loops around the current one, with their stacks. Loops are in program order, so a
+        // loop is inside the ones on the stack which don't end before it.
+        let mut stack: Vec<(usize, String)> = vec![];
+        for (id, range) in self.loops.iter().enumerate() {
+            while stack
+                .last()
+                .is_some_and(|&(parent, _)| self.loops[parent].end <= range.start)
+            {
+                stack.pop();
+            }
+            let frame = match positions.and_then(|positions| positions.get(range.start)) {
+                Some((line, column)) => format!("MOO {line}:{column}"),
+                None => format!("MOO #{}", range.start),
+            };
+            let parent = stack.last().map_or(root, |(_, frames)| frames.as_str());
+            let frames = format!("{parent};{frame}");
+            if steps[id] > 0 {
+                writeln!(folded, "{frames} {}", steps[id]).unwrap();
+            }
+            stack.push((id, frames));
+        }
+        folded
+    }
+
+    /// Returns the whole profile as JSON, with the line and the column of each instruction when
+    /// `positions` are known.
+    pub fn to_json(&self, positions: Option<&[(usize, usize)]>) -> Value {
+        let position = |index: usize| {
+            let position = positions.and_then(|positions| positions.get(index));
+            match position {
+                Some(&(line, column)) => json!({ "line": line, "column": column }),
+                None => Value::Null,
+            }
+        };
+        let instructions: Vec<_> = self
+            .program
+            .iter()
+            .enumerate()
+            .map(|(index, instruction)| {
+                json!({
+                    "index": index,
+                    "instruction": instruction.as_str(),
+                    "position": position(index),
+                    "count": self.counts[index],
+                })
+            })
+            .collect();
+        let loops: Vec<_> = self
+            .loops()
+            .into_iter()
+            .map(|profile| {
+                json!({
+                    "start": profile.range.start,
+                    "end": profile.range.end - 1,
+                    "position": position(profile.range.start),
+                    "entries": profile.entries,
+                    "iterations": profile.iterations,
+                    "steps": profile.steps,
+                    "seconds": profile.time.as_secs_f64(),
+                    "self_seconds": profile.self_time.as_secs_f64(),
+                })
+            })
+            .collect();
+        let dispatches: Vec<_> = self
+            .dispatches
+            .iter()
+            .map(|(code, count)| json!({ "code": code, "count": count }))
+            .collect();
+        json!({
+            "steps": self.steps(),
+            "seconds": self.time().as_secs_f64(),
+            "instructions": instructions,
+            "loops": loops,
+            "dispatches": dispatches,
+        })
+    }
+}
+
+impl Observer for Profiler {
+    fn before_step(&mut self, interpreter: &Interpreter) {
+        let index = interpreter.program_counter();
+        self.count(index, interpreter.memory()[interpreter.pointer()]);
+    }
+
+    fn on_stop(&mut self, _interpreter: &Interpreter) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::instruction::Instruction::*;
+
+    #[test]
+    fn profile_works() {
+        // MoO MoO MoO
+        // MOO moO OOO MoO MoO MoO MoO MoO MoO mOO mOo MOo moo
+        let source = b"MoO MoO MoO\nMOO moO OOO MoO MoO MoO MoO MoO MoO mOO mOo MOo moo\n";
+        let mut program = vec![IncrementByte; 3];
+        program.extend([BeginLoop, IncrementPointer, SetZero]);
+        program.extend([IncrementByte; 6]);
+        program.extend([ExecuteValue, DecrementPointer, DecrementByte, EndLoop]);
+        let profiler = Arc::new(Mutex::new(Profiler::new(&program)));
+        let mut interpreter = Interpreter::new(program).observe(profiler.clone());
+        interpreter.run_with(&mut &b""[..], &mut vec![]).unwrap();
+        let profiler = profiler.lock().unwrap();
+
+        assert_eq!(profiler.counts()[..5], [1, 1, 1, 1, 3]);
+        assert_eq!(profiler.steps(), 40);
+        let loops = profiler.loops();
+        assert_eq!(loops.len(), 1);
+        assert_eq!(loops[0].range, 3..16);
+        assert_eq!((loops[0].entries, loops[0].iterations), (1, 3));
+        assert_eq!(loops[0].steps, 37);
+        // Block 1 holds 6 when `mOO` runs, which increments it.
+        assert_eq!(profiler.dispatches().get(&6), Some(&3));
+
+        let positions: Vec<_> = (1..=3)
+            .map(|token| (1, token * 4 - 3))
+            .chain((1..=13).map(|token| (2, token * 4 - 3)))
+            .collect();
+        let report = profiler.report(Some(&positions), source);
+        assert!(report.starts_with("40 steps in "));
+        assert!(report.contains("#4 (2:5) moO"));
+        assert!(report.contains("#3 (2:1)"));
+        assert!(report.contains("3  6 MoO"));
+
+        let json = profiler.to_json(Some(&positions));
+        assert_eq!(
+            json["loops"][0]["position"],
+            json!({ "line": 2, "column": 1 })
+        );
+        assert_eq!(json["instructions"][4]["count"], 3);
+    }
+
+    #[test]
+    fn folded_works() {
+        // MoO MoO MOO moO MoO MoO MOO MOo moo mOo MOo moo
+        let program = vec![
+            IncrementByte,
+            IncrementByte,
+            BeginLoop,
+            IncrementPointer,
+            IncrementByte,
+            IncrementByte,
+            BeginLoop,
+            DecrementByte,
+            EndLoop,
+            DecrementPointer,
+            DecrementByte,
+            EndLoop,
+        ];
+        let positions: Vec<_> = (0..program.len()).map(|index| (1, index * 4 + 1)).collect();
+        let profiler = Arc::new(Mutex::new(Profiler::new(&program)));
+        let mut interpreter = Interpreter::new(program).observe(profiler.clone());
+        interpreter.run_with(&mut &b""[..], &mut vec![]).unwrap();
+        let profiler = profiler.lock().unwrap();
+
+        assert_eq!(
+            profiler.folded("loops.cow", Some(&positions)),
+            "loops.cow 2\nloops.cow;MOO 1:9 13\nloops.cow;MOO 1:9;MOO 1:25 10\n"
+        );
+        assert_eq!(
+            profiler.folded("loops.cow", None),
+            "loops.cow 2\nloops.cow;MOO #2 13\nloops.cow;MOO #2;MOO #6 10\n"
+        );
+    }
+}
@@ -0,0 +1,252 @@
+use std::{
+    io::{BufRead, Read, Write},
+    path::Path,
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{instruction::Instruction, interpreter::Interpreter, lexer::Lexer, view};
+
+/// Number of memory blocks shown on each side of the pointer.
+const RADIUS: usize = 5;
+
+/// Number of states kept for `:undo`.
+const HISTORY_LIMIT: usize = 100;
+
+const HELP: &str = "\
+Lines of code run against the same memory, pointer and register.
+A line opening a loop continues on the next lines until the loop is closed.
+
+:mem [START] [END]  print the memory blocks from START to END
+:load PATH          run a file
+:undo               go back to the state before the last change
+:reset              clear the memory, the pointer and the register
+:help               print this message
+:quit               leave";
+
+/// What [`Repl::eval`] expects next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// A new line of code or a command.
+    Ready,
+    /// The rest of a loop which was opened but not closed yet.
+    Continue,
+    /// Nothing, as the user left.
+    Quit,
+}
+
+/// Runs lines of code one by one against a persistent [`Interpreter`].
+pub struct Repl {
+    /// Lexes each line, with the options of the session.
+    lexer: Lexer,
+    interpreter: Interpreter,
+    /// States before each change, latest last.
+    history: Vec<Interpreter>,
+    /// Lines of a loop which isn't closed yet.
+    pending: String,
+}
+
+impl Repl {
+    pub fn new(lexer: Lexer) -> Self {
+        Self {
+            lexer,
+            interpreter: Interpreter::new(vec![]),
+            history: vec![],
+            pending: String::new(),
+        }
+    }
+
+    pub fn interpreter(&self) -> &Interpreter {
+        &self.interpreter
+    }
+
+    /// Runs a line of code or a command, reading the input of the program from `stdin` and
+    /// writing its output, as well as the tape, to `stdout`.
+    ///
+    /// If the program fails, the state is rolled back to what it was before the line.
+    pub fn eval<R, W>(&mut self, line: &str, stdin: &mut R, stdout: &mut W) -> Result<Status>
+    where
+        R: BufRead + Read,
+        W: Write,
+    {
+        let trimmed = line.trim();
+        if self.pending.is_empty() {
+            if let Some(command) = trimmed.strip_prefix(':') {
+                return self.command(command, stdin, stdout);
+            }
+        }
+
+        self.pending.push_str(line);
+        self.pending.push('\n');
+        let lexer = self.lexer.with_source(self.pending.clone().into_bytes());
+        let depth = lexer
+            .tokens()
+            .iter()
+            .fold(0isize, |depth, token| match token.command {
+                command if command.is_begin_loop() => depth + 1,
+                command if command.is_end_loop() => depth - 1,
+                _ => depth,
+            });
+        if depth > 0 {
+            return Ok(Status::Continue);
+        }
+        self.pending.clear();
+
+        let program = lexer.lex()?;
+        if !program.is_empty() {
+            self.execute(program, stdin, stdout)?;
+        }
+        Ok(Status::Ready)
+    }
+
+    fn command<R, W>(&mut self, command: &str, stdin: &mut R, stdout: &mut W) -> Result<Status>
+    where
+        R: BufRead + Read,
+        W: Write,
+    {
+        let words: Vec<_> = command.split_whitespace().collect();
+        match words[..] {
+            ["q" | "quit"] => return Ok(Status::Quit),
+            ["h" | "help"] => writeln!(stdout, "{HELP}")?,
+            ["reset"] => {
+                let previous = std::mem::replace(&mut self.interpreter, Interpreter::new(vec![]));
+                self.remember(previous);
+                writeln!(stdout, "{}", self.tape())?;
+            }
+            ["undo"] => match self.history.pop() {
+                Some(previous) => {
+                    self.interpreter = previous;
+                    writeln!(stdout, "{}", self.tape())?;
+                }
+                None => bail!("Nothing to undo"),
+            },
+            ["mem", ref range @ ..] => {
+                let range = view::parse_range(range, &self.interpreter)?;
+                write!(stdout, "{}", view::table(&self.interpreter, range))?;
+            }
+            ["load", path] => {
+                let bytes = std::fs::read(Path::new(path))
+                    .with_context(|| format!("Failed to read `{path}`"))?;
+                let program = self.lexer.with_source(bytes).lex()?;
+                self.execute(program, stdin, stdout)?;
+            }
+            _ => bail!("Unknown command `:{command}`, try `:help`"),
+        }
+        Ok(Status::Ready)
+    }
+
+    /// Runs `program` from the current state, then prints the tape.
+    fn execute<R, W>(
+        &mut self,
+        program: Vec<Instruction>,
+        stdin: &mut R,
+        stdout: &mut W,
+    ) -> Result<()>
+    where
+        R: BufRead + Read,
+        W: Write,
+    {
+        let previous = self.interpreter.clone();
+        self.interpreter.load(program);
+        let mut output = Tracking {
+            inner: stdout,
+            last: None,
+        };
+        let result = self.interpreter.run_with(stdin, &mut output);
+        if output.last.is_some_and(|byte| byte != b'\n') {
+            writeln!(output.inner)?;
+        }
+        output.inner.flush()?;
+        if let Err(e) = result {
+            self.interpreter = previous;
+            return Err(e);
+        }
+        self.remember(previous);
+        writeln!(stdout, "{}", self.tape())?;
+        Ok(())
+    }
+
+    fn remember(&mut self, state: Interpreter) {
+        if self.history.len() == HISTORY_LIMIT {
+            self.history.remove(0);
+        }
+        self.history.push(state);
+    }
+
+    /// Renders the memory blocks around the pointer, along with the register.
+    pub fn tape(&self) -> String {
+        view::tape(&self.interpreter, RADIUS)
+    }
+}
+
+/// Remembers the last byte written, to know whether the output ended its line.
+struct Tracking<'a, W> {
+    inner: &'a mut W,
+    last: Option<u8>,
+}
+
+impl<W: Write> Write for Tracking<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if written > 0 {
+            self.last = Some(buf[written - 1]);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(repl: &mut Repl, line: &str) -> (Result<Status>, String) {
+        let mut output = vec![];
+        let status = repl.eval(line, &mut &b""[..], &mut output);
+        (status, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn eval_keeps_the_state() {
+        let mut repl = Repl::new(Lexer::from_bytes(vec![]));
+
+        let (status, output) = eval(&mut repl, "MoO MoO moO");
+        assert_eq!(status.unwrap(), Status::Ready);
+        assert_eq!(output, "0..=6: 2 [0] 0 0 0 0 0 | pointer 1 | register -\n");
+
+        let (_, output) = eval(&mut repl, "mOo MMM OOM");
+        assert_eq!(output, "2\n0..=5: [2] 0 0 0 0 0 | pointer 0 | register 2\n");
+
+        let (_, output) = eval(&mut repl, ":undo");
+        assert_eq!(output, "0..=6: 2 [0] 0 0 0 0 0 | pointer 1 | register -\n");
+    }
+
+    #[test]
+    fn eval_continues_loops() {
+        let mut repl = Repl::new(Lexer::from_bytes(vec![]));
+        eval(&mut repl, "MoO MoO MoO").0.unwrap();
+
+        assert_eq!(eval(&mut repl, "MOO moO MoO").0.unwrap(), Status::Continue);
+        assert_eq!(eval(&mut repl, "mOo MOo").0.unwrap(), Status::Continue);
+        let (status, output) = eval(&mut repl, "moo");
+        assert_eq!(status.unwrap(), Status::Ready);
+        assert_eq!(output, "0..=5: [0] 3 0 0 0 0 | pointer 0 | register -\n");
+    }
+
+    #[test]
+    fn eval_rolls_back_failures() {
+        let mut repl = Repl::new(Lexer::from_bytes(vec![]));
+        eval(&mut repl, "MoO").0.unwrap();
+
+        assert!(eval(&mut repl, "moO mOo mOo").0.is_err());
+        assert_eq!(repl.interpreter().memory()[0], 1);
+        assert_eq!(repl.interpreter().pointer(), 0);
+
+        let (_, output) = eval(&mut repl, ":mem 0 12");
+        assert_eq!(output, " 0: [1] 0 0 0 0 0 0 0 0 0\n10: 0 0 0\n");
+        assert!(eval(&mut repl, ":nope").0.is_err());
+    }
+}
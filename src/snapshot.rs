@@ -0,0 +1,359 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, ensure, Context, Result};
+
+use crate::{
+    errors::ErrorKind,
+    interpreter::{Interpreter, MEMORY_SIZE},
+    observer::Observer,
+};
+
+/// The first bytes of a state file.
+const MAGIC: &[u8; 4] = b"COWS";
+/// Version of the state file format, bumped whenever it changes.
+const VERSION: u32 = 2;
+
+/// The state of an [`Interpreter`], saved with [`Snapshot::save`] to go on later with
+/// [`Interpreter::restore`].
+///
+/// A state file holds, in little endian:
+///
+/// | Field | Type |
+/// |-------|------|
+/// | `COWS` | 4 bytes |
+/// | Format version | `u32` |
+/// | Hash of the program, see [`instruction::hash`](crate::instruction::hash) | `u64` |
+/// | Length of the program | `u64` |
+/// | Program counter | `u64` |
+/// | Pointer | `u64` |
+/// | 1 and the register, or 0 and 0 if it's empty | `u8`, `i32` |
+/// | Number of bytes of input read | `u64` |
+/// | Path to the source, as UTF-8 | `u32` length, bytes |
+/// | 1 and the dialect, or 0 | `u8`, `u32` length, bytes |
+/// | Strict mode | `u8` |
+/// | 1 and the comment delimiters, or 0 | `u8`, `u32` length, bytes, `u32` length, bytes |
+/// | Memory, without the zeros at the end | `u32` length, `i32`s |
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    /// Path to the source of the program, to read it again on resume.
+    pub source_path: PathBuf,
+    /// How the source was lexed, to lex it the same way on resume.
+    pub source_options: SourceOptions,
+    pub(crate) program_hash: u64,
+    pub(crate) program_length: usize,
+    pub(crate) program_counter: usize,
+    pub(crate) pointer: usize,
+    pub(crate) register: Option<i32>,
+    pub(crate) input_position: u64,
+    pub(crate) memory: Vec<i32>,
+}
+
+/// How the source of a [`Snapshot`] is lexed, see [`Lexer`](crate::lexer::Lexer).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceOptions {
+    /// The name of a built-in dialect or the path to a TOML token table, or `None` to guess it
+    /// from the extension of the source.
+    pub dialect: Option<String>,
+    pub strict: bool,
+    /// The comment delimiters, if any.
+    pub comment: Option<(String, String)>,
+}
+
+impl Snapshot {
+    /// Number of bytes of input read before the snapshot, to be skipped on resume.
+    pub fn input_position(&self) -> u64 {
+        self.input_position
+    }
+
+    /// Reads a state saved with [`Snapshot::save`].
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes =
+            std::fs::read(path).with_context(|| format!("Failed to read `{}`", path.display()))?;
+        Self::from_bytes(&bytes)
+            .with_context(|| format!("`{}` isn't a state saved by cowi", path.display()))
+    }
+
+    /// Writes the state to `path`, through a temporary file so that an interrupted write
+    /// doesn't lose the state saved before.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        std::fs::write(&temporary, self.to_bytes())
+            .and_then(|_| std::fs::rename(&temporary, path))
+            .with_context(|| format!("Failed to write `{}`", path.display()))
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(VERSION.to_le_bytes());
+        bytes.extend(self.program_hash.to_le_bytes());
+        bytes.extend((self.program_length as u64).to_le_bytes());
+        bytes.extend((self.program_counter as u64).to_le_bytes());
+        bytes.extend((self.pointer as u64).to_le_bytes());
+        bytes.push(self.register.is_some() as u8);
+        bytes.extend(self.register.unwrap_or(0).to_le_bytes());
+        bytes.extend(self.input_position.to_le_bytes());
+        push_string(&mut bytes, &self.source_path.to_string_lossy());
+        let options = &self.source_options;
+        bytes.push(options.dialect.is_some() as u8);
+        if let Some(dialect) = &options.dialect {
+            push_string(&mut bytes, dialect);
+        }
+        bytes.push(options.strict as u8);
+        bytes.push(options.comment.is_some() as u8);
+        if let Some((start, end)) = &options.comment {
+            push_string(&mut bytes, start);
+            push_string(&mut bytes, end);
+        }
+        let length = self
+            .memory
+            .iter()
+            .rposition(|&value| value != 0)
+            .map_or(0, |i| i + 1);
+        bytes.extend((length as u32).to_le_bytes());
+        for value in &self.memory[..length] {
+            bytes.extend(value.to_le_bytes());
+        }
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader(bytes);
+        ensure!(reader.take(4)? == MAGIC, "It doesn't start with `COWS`");
+        let version = u32::from_le_bytes(reader.array()?);
+        if version != VERSION {
+            bail!("Its format version is {version}, but only {VERSION} can be read");
+        }
+        let program_hash = u64::from_le_bytes(reader.array()?);
+        let program_length = reader.index()?;
+        let program_counter = reader.index()?;
+        let pointer = reader.index()?;
+        ensure!(pointer < MEMORY_SIZE, "The pointer is off the memory");
+        let register = match reader.take(1)?[0] {
+            0 => {
+                reader.take(4)?;
+                None
+            }
+            _ => Some(i32::from_le_bytes(reader.array()?)),
+        };
+        let input_position = u64::from_le_bytes(reader.array()?);
+        let source_path = reader.string()?.into();
+        let dialect = match reader.take(1)?[0] {
+            0 => None,
+            _ => Some(reader.string()?.into()),
+        };
+        let strict = reader.take(1)?[0] != 0;
+        let comment = match reader.take(1)?[0] {
+            0 => None,
+            _ => Some((reader.string()?.into(), reader.string()?.into())),
+        };
+        let length = u32::from_le_bytes(reader.array()?) as usize;
+        ensure!(length <= MEMORY_SIZE, "The memory is too large");
+        let mut memory = vec![0; MEMORY_SIZE];
+        for value in &mut memory[..length] {
+            *value = i32::from_le_bytes(reader.array()?);
+        }
+        ensure!(reader.0.is_empty(), "It goes on after the memory");
+
+        Ok(Self {
+            source_path,
+            source_options: SourceOptions {
+                dialect,
+                strict,
+                comment,
+            },
+            program_hash,
+            program_length,
+            program_counter,
+            pointer,
+            register,
+            input_position,
+            memory,
+        })
+    }
+}
+
+/// Writes a string field of a state file.
+fn push_string(bytes: &mut Vec<u8>, text: &str) {
+    bytes.extend((text.len() as u32).to_le_bytes());
+    bytes.extend(text.as_bytes());
+}
+
+/// Reads the fields of a state file one after another.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8]> {
+        ensure!(length <= self.0.len(), "It ends too early");
+        let (taken, rest) = self.0.split_at(length);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(self.take(N)?.try_into()?)
+    }
+
+    fn index(&mut self) -> Result<usize> {
+        Ok(u64::from_le_bytes(self.array()?).try_into()?)
+    }
+
+    fn string(&mut self) -> Result<&'a str> {
+        let length = u32::from_le_bytes(self.array()?) as usize;
+        Ok(std::str::from_utf8(self.take(length)?)?)
+    }
+}
+
+/// An [`Observer`] saving the state every `interval` steps and when the program is interrupted,
+/// so that a long run can go on from there if it's stopped.
+///
+/// When the program fails, its state is saved next to the last checkpoint, with `.failed` added
+/// to the path, as going on from there would fail again.
+///
+/// Saving errors are kept until [`Checkpointer::finish`], as observers can't stop the
+/// interpreter.
+pub struct Checkpointer {
+    path: PathBuf,
+    source_path: PathBuf,
+    source_options: SourceOptions,
+    interval: u64,
+    steps: u64,
+    error: Option<anyhow::Error>,
+}
+
+impl Checkpointer {
+    /// Saves to `path` the states of the program read from `source_path`.
+    pub fn new(path: PathBuf, source_path: PathBuf, interval: u64) -> Self {
+        Self {
+            path,
+            source_path,
+            source_options: SourceOptions::default(),
+            interval: interval.max(1),
+            steps: 0,
+            error: None,
+        }
+    }
+
+    /// Saves with the states how the source is lexed.
+    pub fn source_options(mut self, options: SourceOptions) -> Self {
+        self.source_options = options;
+        self
+    }
+
+    /// Path to the state of a program which failed.
+    pub fn failed_path(&self) -> PathBuf {
+        let mut path = self.path.as_os_str().to_owned();
+        path.push(".failed");
+        path.into()
+    }
+
+    /// Returns the first error met while saving the state.
+    pub fn finish(&mut self) -> Result<()> {
+        self.error.take().map_or(Ok(()), Err)
+    }
+
+    fn save(&mut self, interpreter: &Interpreter, path: &Path) {
+        if self.error.is_none() {
+            let mut snapshot = interpreter.snapshot(&self.source_path);
+            snapshot.source_options = self.source_options.clone();
+            self.error = snapshot.save(path).err();
+        }
+    }
+}
+
+impl Observer for Checkpointer {
+    fn after_step(&mut self, interpreter: &Interpreter, _program_counter: usize) {
+        self.steps += 1;
+        if self.steps.is_multiple_of(self.interval) {
+            self.save(interpreter, &self.path.clone());
+        }
+    }
+
+    fn on_error(&mut self, interpreter: &Interpreter, error: &anyhow::Error) {
+        let path = match error.downcast_ref::<ErrorKind>() {
+            Some(ErrorKind::Interrupted) => self.path.clone(),
+            _ => self.failed_path(),
+        };
+        self.save(interpreter, &path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::{
+        instruction::Instruction::*,
+        interpreter::{Event, Interpreter},
+    };
+
+    #[test]
+    fn snapshot_works() {
+        // MoO MoO MMM moO Moo MMM OOM
+        let program = vec![
+            IncrementByte,
+            IncrementByte,
+            CopyOrPaste,
+            IncrementPointer,
+            ReadOrWrite,
+            CopyOrPaste,
+            WriteStdout,
+        ];
+        let mut interpreter = Interpreter::new(program.clone());
+        interpreter.push_input(b"A");
+        for _ in 0..5 {
+            assert!(!matches!(interpreter.step(), Event::Error(_)));
+        }
+
+        let path = std::env::temp_dir().join(format!("cowi-{}.state", std::process::id()));
+        let mut snapshot = interpreter.snapshot(Path::new("program.cow"));
+        snapshot.source_options = SourceOptions {
+            dialect: Some("ook".into()),
+            strict: true,
+            comment: Some(("[[".into(), "]]".into())),
+        };
+        snapshot.save(&path).unwrap();
+        let loaded = Snapshot::load(&path).unwrap();
+        assert_eq!(loaded, snapshot);
+        assert_eq!(loaded.input_position(), 1);
+
+        let mut resumed = Interpreter::new(program.clone());
+        resumed.restore(&loaded).unwrap();
+        let mut output = vec![];
+        resumed.run_with(&mut &b""[..], &mut output).unwrap();
+        assert_eq!(output, b"2");
+        assert_eq!(&resumed.memory()[..2], [2, 2]);
+
+        let mut other = Interpreter::new(program[1..].to_vec());
+        assert!(other.restore(&loaded).is_err());
+
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[4] = VERSION as u8 + 1;
+        std::fs::write(&path, bytes).unwrap();
+        assert!(Snapshot::load(&path).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn checkpointer_keeps_last_good_state() {
+        // MoO MoO MoO mOO
+        let program = vec![IncrementByte, IncrementByte, IncrementByte, ExecuteValue];
+        let path = std::env::temp_dir().join(format!("cowi-{}-failing.state", std::process::id()));
+        let checkpointer = Checkpointer::new(path.clone(), "program.cow".into(), 2);
+        let failed_path = checkpointer.failed_path();
+        let checkpointer = Arc::new(Mutex::new(checkpointer));
+        let mut interpreter = Interpreter::new(program).observe(checkpointer.clone());
+        assert!(interpreter.run_with(&mut &b""[..], &mut vec![]).is_err());
+        checkpointer.lock().unwrap().finish().unwrap();
+
+        let last = Snapshot::load(&path).unwrap();
+        assert_eq!(last.program_counter, 2);
+        assert_eq!(last.memory[0], 2);
+        let failed = Snapshot::load(&failed_path).unwrap();
+        assert_eq!(failed.program_counter, 3);
+        assert_eq!(failed.memory[0], 3);
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(failed_path).unwrap();
+    }
+}
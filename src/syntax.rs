@@ -0,0 +1,273 @@
+use std::ops::Range;
+
+use crate::{dialect::Command, lexer::Lexer};
+
+/// What a piece of the source is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    /// A token standing for a command.
+    Token(Command),
+    /// The byte order mark at the beginning of the source.
+    Bom,
+    Whitespace,
+    /// A comment between the comment delimiters of the lexer, including them.
+    Comment,
+    /// Any other bytes, such as prose or tokens glued to other letters in strict mode.
+    Text,
+}
+
+/// A piece of the source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Element {
+    pub kind: Kind,
+    /// Byte range in the source, including the byte order mark.
+    pub range: Range<usize>,
+}
+
+impl Element {
+    pub fn is_trivia(&self) -> bool {
+        !matches!(self.kind, Kind::Token(_))
+    }
+}
+
+/// A structured view of the elements, with `MOO`…`moo` grouped into loops.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    Element(Element),
+    Loop {
+        begin: Element,
+        children: Vec<Node>,
+        /// `None` if the loop isn't closed before the end of the source.
+        end: Option<Element>,
+    },
+}
+
+/// A lossless syntax tree: every byte of the source belongs to exactly one element, so printing
+/// the elements in order gives back the source byte for byte.
+#[derive(Debug, Clone)]
+pub struct SyntaxTree {
+    source: Vec<u8>,
+    elements: Vec<Element>,
+}
+
+impl SyntaxTree {
+    pub fn parse(lexer: &Lexer) -> Self {
+        let bom = lexer.bom().unwrap_or_default();
+        let bytes = lexer.source();
+        let mut source = bom.to_vec();
+        source.extend_from_slice(bytes);
+
+        let mut elements = vec![];
+        if !bom.is_empty() {
+            elements.push(Element {
+                kind: Kind::Bom,
+                range: 0..bom.len(),
+            });
+        }
+        let shift = |range: Range<usize>| range.start + bom.len()..range.end + bom.len();
+
+        let mut position = 0;
+        let tokens = lexer.tokens();
+        let gaps = tokens
+            .iter()
+            .map(|token| (token.offset, Some(token)))
+            .chain([(bytes.len(), None)]);
+        for (offset, token) in gaps {
+            while position < offset {
+                let (kind, end) = match lexer.comment_end(position) {
+                    Some(end) => (Kind::Comment, end),
+                    None => {
+                        let whitespace = bytes[position].is_ascii_whitespace();
+                        let length = bytes[position..offset]
+                            .iter()
+                            .enumerate()
+                            .take_while(|&(index, byte)| {
+                                byte.is_ascii_whitespace() == whitespace
+                                    && (index == 0 || lexer.comment_end(position + index).is_none())
+                            })
+                            .count();
+                        let kind = if whitespace {
+                            Kind::Whitespace
+                        } else {
+                            Kind::Text
+                        };
+                        (kind, position + length)
+                    }
+                };
+                elements.push(Element {
+                    kind,
+                    range: shift(position..end),
+                });
+                position = end;
+            }
+            if let Some(token) = token {
+                position = token.offset + token.length;
+                elements.push(Element {
+                    kind: Kind::Token(token.command),
+                    range: shift(token.offset..position),
+                });
+            }
+        }
+
+        Self { source, elements }
+    }
+
+    pub fn elements(&self) -> &[Element] {
+        &self.elements
+    }
+
+    /// Returns the bytes of `element`.
+    pub fn text(&self, element: &Element) -> &[u8] {
+        &self.source[element.range.clone()]
+    }
+
+    /// Prints the tree back into source code.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.elements
+            .iter()
+            .flat_map(|element| self.text(element))
+            .copied()
+            .collect()
+    }
+
+    /// Groups the elements into loops, matching `MOO` and `moo` like brackets. A `moo` without
+    /// a `MOO` is kept as a plain element.
+    ///
+    /// Note that the interpreter matches loops differently when the command right after `MOO`
+    /// or right before `moo` is a loop command too.
+    pub fn nodes(&self) -> Vec<Node> {
+        // Children of the open loops, outermost first, along with their `MOO`.
+        let mut stack: Vec<(Option<Element>, Vec<Node>)> = vec![(None, vec![])];
+        for element in &self.elements {
+            match element.kind {
+                Kind::Token(command) if command.is_begin_loop() => {
+                    stack.push((Some(element.clone()), vec![]));
+                }
+                Kind::Token(command) if command.is_end_loop() && stack.len() > 1 => {
+                    let (begin, children) = stack.pop().unwrap();
+                    stack.last_mut().unwrap().1.push(Node::Loop {
+                        begin: begin.unwrap(),
+                        children,
+                        end: Some(element.clone()),
+                    });
+                }
+                _ => stack
+                    .last_mut()
+                    .unwrap()
+                    .1
+                    .push(Node::Element(element.clone())),
+            }
+        }
+        while stack.len() > 1 {
+            let (begin, children) = stack.pop().unwrap();
+            stack.last_mut().unwrap().1.push(Node::Loop {
+                begin: begin.unwrap(),
+                children,
+                end: None,
+            });
+        }
+        stack.pop().unwrap().1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruction::Instruction::*;
+
+    #[test]
+    fn parse_works() {
+        let lexer = Lexer::from_bytes(b"\xef\xbb\xbfMoO [[ MOO ]]\r\nmoon!".to_vec())
+            .comment("[[", "]]")
+            .strict(true);
+        let tree = SyntaxTree::parse(&lexer);
+        let kinds: Vec<_> = tree
+            .elements()
+            .iter()
+            .map(|element| (element.kind, element.range.clone()))
+            .collect();
+
+        assert_eq!(
+            kinds,
+            vec![
+                (Kind::Bom, 0..3),
+                (Kind::Token(Command::Cow(IncrementByte)), 3..6),
+                (Kind::Whitespace, 6..7),
+                (Kind::Comment, 7..16),
+                (Kind::Whitespace, 16..18),
+                (Kind::Text, 18..23),
+            ]
+        );
+        assert_eq!(tree.to_bytes(), b"\xef\xbb\xbfMoO [[ MOO ]]\r\nmoon!");
+    }
+
+    #[test]
+    fn to_bytes_is_lossless() {
+        for source in [
+            &include_bytes!("../samples/hello_world.cow")[..],
+            include_bytes!("../samples/mandelbrot.cow"),
+            b"\xef\xbb\xbf",
+            b"",
+            b"[[ open",
+            b"MoO\xffmoo",
+        ] {
+            let lexer = Lexer::from_bytes(source.to_vec()).comment("[[", "]]");
+            assert_eq!(SyntaxTree::parse(&lexer).to_bytes(), source);
+        }
+    }
+
+    #[test]
+    fn nodes_works() {
+        let lexer = Lexer::from_bytes(b"moo MOO MoO MOO moo moo MOO".to_vec());
+        let tree = SyntaxTree::parse(&lexer);
+        let token = |range: Range<usize>, instruction| Element {
+            kind: Kind::Token(Command::Cow(instruction)),
+            range,
+        };
+        let space = |start: usize| {
+            Node::Element(Element {
+                kind: Kind::Whitespace,
+                range: start..start + 1,
+            })
+        };
+
+        assert_eq!(
+            tree.nodes(),
+            vec![
+                Node::Element(token(0..3, EndLoop)),
+                space(3),
+                Node::Loop {
+                    begin: token(4..7, BeginLoop),
+                    children: vec![
+                        space(7),
+                        Node::Element(token(8..11, IncrementByte)),
+                        space(11),
+                        Node::Loop {
+                            begin: token(12..15, BeginLoop),
+                            children: vec![space(15)],
+                            end: Some(token(16..19, EndLoop)),
+                        },
+                        space(19),
+                    ],
+                    end: Some(token(20..23, EndLoop)),
+                },
+                space(23),
+                Node::Loop {
+                    begin: token(24..27, BeginLoop),
+                    children: vec![],
+                    end: None,
+                },
+            ]
+        );
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn to_bytes_is_always_lossless(source in "[mMoO \r\n\\[\\]x]{0,64}", strict: bool) {
+            let lexer = Lexer::from_bytes(source.clone().into_bytes())
+                .comment("[[", "]]")
+                .strict(strict);
+            proptest::prop_assert_eq!(SyntaxTree::parse(&lexer).to_bytes(), source.into_bytes());
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use std::{
+    io::{self, Write},
+    ops::Range,
+};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::{
+    instruction::Instruction,
+    interpreter::Interpreter,
+    observer::{Io, Observer},
+};
+
+/// Which steps a [`Tracer`] writes.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    /// Only steps whose program counter is in this range.
+    pub program_counters: Option<Range<usize>>,
+    /// Only steps running one of these instructions. `mOO` is matched as itself, not as the
+    /// instruction it executes.
+    pub instructions: Option<Vec<Instruction>>,
+}
+
+impl Filter {
+    fn matches(&self, program_counter: usize, instruction: Instruction) -> bool {
+        self.program_counters
+            .as_ref()
+            .is_none_or(|range| range.contains(&program_counter))
+            && self
+                .instructions
+                .as_ref()
+                .is_none_or(|instructions| instructions.contains(&instruction))
+    }
+}
+
+/// A step as written by a [`Tracer`].
+#[derive(Debug, Serialize)]
+struct Record {
+    /// Number of steps run before this one, traced or not.
+    step: u64,
+    pc: usize,
+    instruction: &'static str,
+    /// Index of the current memory block when the step started.
+    pointer: usize,
+    /// Value of the memory block at `pointer` before the step.
+    before: i32,
+    /// Value of the memory block at `pointer` after the step.
+    after: i32,
+    /// The register after the step.
+    register: Option<i32>,
+    /// The byte read by `Moo`, or the integer read by `oom`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    read: Option<i32>,
+    /// The byte written by `Moo`, or the integer written by `OOM`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    write: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// An [`Observer`] writing a JSON line per step that passes its filter, e.g.
+///
+/// ```text
+/// {"step":0,"pc":0,"instruction":"MoO","pointer":0,"before":0,"after":1,"register":null}
+/// ```
+///
+/// Writing errors are kept until [`Tracer::finish`], as observers can't stop the interpreter.
+pub struct Tracer<W> {
+    output: W,
+    filter: Filter,
+    steps: u64,
+    /// The step running, if it passes the filter.
+    record: Option<Record>,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(output: W, filter: Filter) -> Self {
+        Self {
+            output,
+            filter,
+            steps: 0,
+            record: None,
+            error: None,
+        }
+    }
+
+    /// Flushes the output, and returns the first error met while writing the trace.
+    pub fn finish(&mut self) -> Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e).context("Failed to write the trace");
+        }
+        self.output.flush().context("Failed to write the trace")
+    }
+
+    fn write(&mut self, interpreter: &Interpreter, error: Option<&anyhow::Error>) {
+        let Some(mut record) = self.record.take() else {
+            return;
+        };
+        record.after = interpreter.memory()[record.pointer];
+        record.register = interpreter.register();
+        record.error = error.map(|e| e.to_string());
+        if self.error.is_some() {
+            return;
+        }
+        let result = serde_json::to_writer(&mut self.output, &record)
+            .map_err(io::Error::from)
+            .and_then(|_| self.output.write_all(b"\n"));
+        self.error = result.err();
+    }
+}
+
+impl<W: Write> Observer for Tracer<W> {
+    fn before_step(&mut self, interpreter: &Interpreter) {
+        let step = self.steps;
+        self.steps += 1;
+        let pc = interpreter.program_counter();
+        let instruction = interpreter.program()[pc];
+        self.record = self.filter.matches(pc, instruction).then(|| {
+            let pointer = interpreter.pointer();
+            Record {
+                step,
+                pc,
+                instruction: instruction.as_str(),
+                pointer,
+                before: interpreter.memory()[pointer],
+                after: 0,
+                register: None,
+                read: None,
+                write: None,
+                error: None,
+            }
+        });
+    }
+
+    fn after_step(&mut self, interpreter: &Interpreter, _program_counter: usize) {
+        self.write(interpreter, None);
+    }
+
+    fn on_io(&mut self, _interpreter: &Interpreter, io: Io) {
+        if let Some(record) = &mut self.record {
+            match io {
+                Io::ReadByte(byte) => record.read = Some(byte.into()),
+                Io::ReadInteger(integer) => record.read = Some(integer),
+                Io::WriteByte(byte) => record.write = Some(byte.into()),
+                Io::WriteInteger(integer) => record.write = Some(integer),
+            }
+        }
+    }
+
+    fn on_error(&mut self, interpreter: &Interpreter, error: &anyhow::Error) {
+        self.write(interpreter, Some(error));
+    }
+}
+
+/// Parses a range of program counters such as `10..20`, `10..=20`, `10..`, `..20` or `10`.
+pub fn parse_range(text: &str) -> Result<Range<usize>> {
+    let bound = |text: &str, default: usize| -> Result<usize> {
+        if text.is_empty() {
+            return Ok(default);
+        }
+        text.parse()
+            .with_context(|| format!("`{text}` isn't an instruction index"))
+    };
+    Ok(match text.split_once("..") {
+        Some((start, end)) => match end.strip_prefix('=') {
+            // There is no instruction at `usize::MAX`, so an end there can be left out.
+            Some(end) => bound(start, 0)?..bound(end, usize::MAX - 1)?.saturating_add(1),
+            None => bound(start, 0)?..bound(end, usize::MAX)?,
+        },
+        None => {
+            let index = bound(text, 0)?;
+            index..index.saturating_add(1)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::instruction::Instruction::*;
+
+    #[test]
+    fn trace_works() {
+        // MoO MoO Moo MMM OOO Moo OOM
+        let program = vec![
+            IncrementByte,
+            IncrementByte,
+            ReadOrWrite,
+            CopyOrPaste,
+            SetZero,
+            ReadOrWrite,
+            WriteStdout,
+        ];
+        let filter = Filter {
+            program_counters: Some(parse_range("2..").unwrap()),
+            instructions: Some(vec![ReadOrWrite, CopyOrPaste, WriteStdout]),
+        };
+        let tracer = Arc::new(Mutex::new(Tracer::new(vec![], filter)));
+        let mut interpreter = Interpreter::new(program).observe(tracer.clone());
+        interpreter.run_with(&mut &b"A"[..], &mut vec![]).unwrap();
+
+        let trace = String::from_utf8(tracer.lock().unwrap().output.clone()).unwrap();
+        let lines: Vec<_> = trace.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                r#"{"step":2,"pc":2,"instruction":"Moo","pointer":0,"before":2,"after":2,"register":null,"write":2}"#,
+                r#"{"step":3,"pc":3,"instruction":"MMM","pointer":0,"before":2,"after":2,"register":2}"#,
+                r#"{"step":5,"pc":5,"instruction":"Moo","pointer":0,"before":0,"after":65,"register":2,"read":65}"#,
+                r#"{"step":6,"pc":6,"instruction":"OOM","pointer":0,"before":65,"after":65,"register":2,"write":65}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_range_works() {
+        assert_eq!(parse_range("10..20").unwrap(), 10..20);
+        assert_eq!(parse_range("10..=20").unwrap(), 10..21);
+        assert_eq!(parse_range("..20").unwrap(), 0..20);
+        assert_eq!(parse_range("10..").unwrap(), 10..usize::MAX);
+        assert_eq!(parse_range("7").unwrap(), 7..8);
+        assert_eq!(
+            parse_range("0..=18446744073709551615").unwrap(),
+            0..usize::MAX
+        );
+        assert_eq!(
+            parse_range("18446744073709551615").unwrap(),
+            usize::MAX..usize::MAX
+        );
+        assert!(parse_range("a..b").is_err());
+    }
+}
@@ -0,0 +1,329 @@
+use crate::{
+    errors::TranslateError,
+    instruction::Instruction::{self, *},
+};
+
+/// `MoO MOo` - a no-op placed between two adjacent loop commands.
+///
+/// `MOO` skips the command right after it and `moo` skips the command right before it when
+/// searching for their counterpart, so `MOO MOO`, `MOO moo` and `moo moo` would be matched
+/// wrongly without it.
+pub(crate) const PADDING: [Instruction; 2] = [IncrementByte, DecrementByte];
+
+/// Per-cell layout of the Brainfuck tape used to emulate COW commands which need scratch space.
+///
+/// | Offset | Use |
+/// |--------|-----|
+/// | 0 | the COW memory block itself |
+/// | 1 | 1 if the register holds a value (`MMM`) |
+/// | 2 | the register value (`MMM`) |
+/// | 3, 4 | temporaries |
+const STRIDE: usize = 5;
+
+/// Translates a Brainfuck program into COW.
+///
+/// `.` and `,` have no direct equivalent since `Moo` either reads or writes depending on the
+/// current memory block, so they are expanded as follows:
+///
+/// | Brainfuck | COW | |
+/// |-----------|-----|---|
+/// | `,` | `OOO Moo` | clear the block so that `Moo` reads |
+/// | `.` | `MMM MOO Moo OOO moo MMM` | only write when the block isn't 0, then restore it from the register |
+pub fn brainfuck_to_cow(source: &[u8]) -> Result<Vec<Instruction>, TranslateError> {
+    let mut program: Vec<Instruction> = vec![];
+    let mut depth: Vec<usize> = vec![];
+
+    for (index, byte) in source.iter().enumerate() {
+        let instructions: &[Instruction] = match byte {
+            b'+' => &[IncrementByte],
+            b'-' => &[DecrementByte],
+            b'>' => &[IncrementPointer],
+            b'<' => &[DecrementPointer],
+            b',' => &[SetZero, ReadOrWrite],
+            b'.' => &[
+                CopyOrPaste,
+                BeginLoop,
+                ReadOrWrite,
+                SetZero,
+                EndLoop,
+                CopyOrPaste,
+            ],
+            b'[' => {
+                depth.push(index);
+                &[BeginLoop]
+            }
+            b']' => {
+                depth.pop().ok_or(TranslateError::UnmatchedLoop(index))?;
+                &[EndLoop]
+            }
+            _ => continue,
+        };
+        for &instruction in instructions {
+            if let Some(&last) = program.last() {
+                if needs_padding(last, instruction) {
+                    program.extend(PADDING);
+                }
+            }
+            program.push(instruction);
+        }
+    }
+
+    if let Some(index) = depth.pop() {
+        return Err(TranslateError::UnmatchedLoop(index));
+    }
+
+    log::debug!("Results of translation: {:?}", program);
+
+    Ok(program)
+}
+
+/// Translates a COW program into Brainfuck.
+///
+/// `moo`, `mOo`, `moO`, `MOo`, `MoO`, `MOO` and `OOO` are translated one to one. `Moo` and `MMM`
+/// are emulated with scratch cells, in which case every COW memory block takes [`STRIDE`]
+/// Brainfuck cells. `mOO`, `OOM` and `oom` have no equivalent and are reported as
+/// [`TranslateError::Unsupported`].
+///
+/// Note that COW memory blocks are 32-bit signed integers, while most Brainfuck implementations
+/// use 8-bit cells.
+pub fn cow_to_brainfuck(program: &[Instruction]) -> Result<String, TranslateError> {
+    let unsupported: Vec<_> = program
+        .iter()
+        .copied()
+        .enumerate()
+        .filter(|(_, instruction)| matches!(instruction, ExecuteValue | WriteStdout | ReadStdin))
+        .collect();
+    if !unsupported.is_empty() {
+        return Err(TranslateError::Unsupported(unsupported));
+    }
+    check_loops(program)?;
+
+    let uses_register = program.contains(&CopyOrPaste);
+    let stride = if uses_register || program.contains(&ReadOrWrite) {
+        STRIDE
+    } else {
+        1
+    };
+
+    let mut output = String::new();
+    for instruction in program {
+        match instruction {
+            EndLoop => output.push(']'),
+            BeginLoop => output.push('['),
+            DecrementByte => output.push('-'),
+            IncrementByte => output.push('+'),
+            SetZero => output.push_str("[-]"),
+            IncrementPointer | DecrementPointer => {
+                let step = if *instruction == IncrementPointer {
+                    '>'
+                } else {
+                    '<'
+                };
+                let back = if *instruction == IncrementPointer {
+                    '<'
+                } else {
+                    '>'
+                };
+                if uses_register {
+                    // Carry the register (offsets 1 and 2) along with the pointer.
+                    let carry = format!(
+                        "[-{}+{}]",
+                        step.to_string().repeat(stride),
+                        back.to_string().repeat(stride)
+                    );
+                    output.push('>');
+                    output.push_str(&carry);
+                    output.push('>');
+                    output.push_str(&carry);
+                    output.push_str("<<");
+                }
+                output.push_str(&step.to_string().repeat(stride));
+            }
+            // if block != 0 { write } else { read }
+            ReadOrWrite => {
+                output.push_str("[->>>+>+<<<<]>>>>[-<<<<+>>>>]+<[[-]<<<.>>>>-<]>[-<<<<,>>>>]<<<<")
+            }
+            // if has_value { block = value; clear register } else { copy block into register }
+            CopyOrPaste => output.push_str(
+                ">>>+<<<>[<[-]>>[-<<+>>]<->>-<<]>>[-<<<[->>+>>+<<<<]>>>>[-<<<<+>>>>]<<<+>>]<<<",
+            ),
+            ExecuteValue | WriteStdout | ReadStdin => unreachable!(),
+        }
+    }
+
+    log::debug!("Results of translation: {}", output);
+
+    Ok(output)
+}
+
+/// Renders a program as COW source code.
+pub fn to_source(program: &[Instruction]) -> String {
+    program
+        .chunks(20)
+        .map(|line| {
+            line.iter()
+                .map(|instruction| instruction.as_str())
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn needs_padding(previous: Instruction, next: Instruction) -> bool {
+    (previous == BeginLoop && next.is_loop()) || (previous.is_loop() && next == EndLoop)
+}
+
+/// Checks that every loop is matched by the interpreter the same way as brackets would be.
+fn check_loops(program: &[Instruction]) -> Result<(), TranslateError> {
+    let mut depth = vec![];
+    for (index, instruction) in program.iter().enumerate() {
+        match instruction {
+            BeginLoop => depth.push(index),
+            EndLoop => {
+                let begin = depth.pop().ok_or(TranslateError::UnmatchedLoop(index))?;
+                if find_end_loop(program, begin) != Some(index)
+                    || find_begin_loop(program, index) != Some(begin)
+                {
+                    return Err(TranslateError::UnmatchedLoop(begin));
+                }
+            }
+            _ => {}
+        }
+    }
+    match depth.pop() {
+        Some(index) => Err(TranslateError::UnmatchedLoop(index)),
+        None => Ok(()),
+    }
+}
+
+/// Same search as `MOO` in the interpreter.
+pub(crate) fn find_end_loop(program: &[Instruction], begin: usize) -> Option<usize> {
+    let mut c = 1;
+    for (pc, instruction) in program.iter().enumerate().skip(begin + 2) {
+        match instruction {
+            BeginLoop => c += 1,
+            EndLoop => c -= 1,
+            _ => {}
+        }
+        if c == 0 {
+            return Some(pc);
+        }
+    }
+    None
+}
+
+/// Same search as `moo` in the interpreter.
+pub(crate) fn find_begin_loop(program: &[Instruction], end: usize) -> Option<usize> {
+    let mut c = 1;
+    for pc in (0..end.checked_sub(1)?).rev() {
+        match program[pc] {
+            BeginLoop => c -= 1,
+            EndLoop => c += 1,
+            _ => {}
+        }
+        if c == 0 {
+            return Some(pc);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn brainfuck_to_cow_works() {
+        assert_eq!(
+            brainfuck_to_cow(b"+-><,. comment").unwrap(),
+            vec![
+                IncrementByte,
+                DecrementByte,
+                IncrementPointer,
+                DecrementPointer,
+                SetZero,
+                ReadOrWrite,
+                CopyOrPaste,
+                BeginLoop,
+                ReadOrWrite,
+                SetZero,
+                EndLoop,
+                CopyOrPaste,
+            ]
+        );
+    }
+
+    #[test]
+    fn brainfuck_to_cow_pads_adjacent_loops() {
+        let program = brainfuck_to_cow(b"[[]]").unwrap();
+
+        assert_eq!(
+            program,
+            vec![
+                BeginLoop,
+                IncrementByte,
+                DecrementByte,
+                BeginLoop,
+                IncrementByte,
+                DecrementByte,
+                EndLoop,
+                IncrementByte,
+                DecrementByte,
+                EndLoop,
+            ]
+        );
+        assert!(check_loops(&program).is_ok());
+    }
+
+    #[test]
+    fn brainfuck_to_cow_rejects_unmatched_brackets() {
+        assert_eq!(
+            brainfuck_to_cow(b"+[").unwrap_err(),
+            TranslateError::UnmatchedLoop(1)
+        );
+        assert_eq!(
+            brainfuck_to_cow(b"]").unwrap_err(),
+            TranslateError::UnmatchedLoop(0)
+        );
+    }
+
+    #[test]
+    fn cow_to_brainfuck_works() {
+        // MoO MOO moO MoO mOo MOo moo OOO
+        let program = vec![
+            IncrementByte,
+            BeginLoop,
+            IncrementPointer,
+            IncrementByte,
+            DecrementPointer,
+            DecrementByte,
+            EndLoop,
+            SetZero,
+        ];
+
+        assert_eq!(cow_to_brainfuck(&program).unwrap(), "+[>+<-][-]");
+    }
+
+    #[test]
+    fn cow_to_brainfuck_lists_unsupported_instructions() {
+        // mOO MoO OOM oom
+        let program = vec![ExecuteValue, IncrementByte, WriteStdout, ReadStdin];
+
+        assert_eq!(
+            cow_to_brainfuck(&program).unwrap_err(),
+            TranslateError::Unsupported(vec![(0, ExecuteValue), (2, WriteStdout), (3, ReadStdin)])
+        );
+    }
+
+    #[test]
+    fn cow_to_brainfuck_rejects_skipped_loop_commands() {
+        // OOO MOO moo moo - `MOO` matches the second `moo`.
+        let program = vec![SetZero, BeginLoop, EndLoop, EndLoop];
+
+        assert_eq!(
+            cow_to_brainfuck(&program).unwrap_err(),
+            TranslateError::UnmatchedLoop(1)
+        );
+    }
+}
@@ -0,0 +1,432 @@
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use ratatui::{
+    backend::Backend,
+    crossterm::event::{
+        self, Event as TerminalEvent, KeyCode, KeyEvent, KeyEventKind, KeyModifiers,
+    },
+    layout::{Constraint, Layout, Rect},
+    style::{Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Paragraph},
+    Frame, Terminal,
+};
+
+use crate::{
+    instruction::Instruction,
+    interpreter::{Event, Interpreter},
+};
+
+/// Slowest and fastest speeds, in steps per second.
+const SPEEDS: (f64, f64) = (1.0, 1_048_576.0);
+/// Time between two frames while the program runs.
+const FRAME: Duration = Duration::from_millis(33);
+/// Number of instructions per line of the source shown for programs without positions.
+const TOKENS_PER_LINE: usize = 10;
+
+/// A live view of a program running: the source with the current instruction highlighted,
+/// the memory around the pointer, the register, the output and the number of steps.
+///
+/// It's driven by [`Tui::handle`] for keys and [`Tui::tick`] for time, and rendered on any
+/// backend with [`Tui::draw`], so that it can be tested without a terminal.
+pub struct Tui {
+    interpreter: Interpreter,
+    /// Lines of the source.
+    lines: Vec<Vec<u8>>,
+    /// 1-based line and column of each instruction.
+    positions: Vec<(usize, usize)>,
+    /// Length in bytes of each instruction, 3 if it isn't known.
+    lengths: Vec<usize>,
+    output: Vec<u8>,
+    steps: u64,
+    /// Steps per second.
+    speed: f64,
+    paused: bool,
+    /// Steps owed for the time elapsed at the current speed.
+    budget: f64,
+    /// `Some` with what was typed so far while the program waits for input.
+    input: Option<String>,
+    /// `Some` with the reason why the program stopped for good.
+    stopped: Option<String>,
+}
+
+impl Tui {
+    /// `source` and `positions` are the source of `program` and the line and column of each
+    /// instruction, as found by
+    /// [`Lexer::instruction_positions`](crate::lexer::Lexer::instruction_positions). Without
+    /// positions, the program is shown as COW instead.
+    pub fn new(
+        program: Vec<Instruction>,
+        source: &[u8],
+        positions: Option<Vec<(usize, usize)>>,
+    ) -> Self {
+        let (lines, positions) = match positions {
+            Some(positions) => (
+                source
+                    .split(|&byte| byte == b'\n')
+                    .map(|line| line.strip_suffix(b"\r").unwrap_or(line).to_vec())
+                    .collect(),
+                positions,
+            ),
+            None => (
+                program
+                    .chunks(TOKENS_PER_LINE)
+                    .map(|line| {
+                        let tokens: Vec<_> = line.iter().map(|token| token.as_str()).collect();
+                        tokens.join(" ").into_bytes()
+                    })
+                    .collect(),
+                (0..program.len())
+                    .map(|index| (index / TOKENS_PER_LINE + 1, index % TOKENS_PER_LINE * 4 + 1))
+                    .collect(),
+            ),
+        };
+        Self {
+            interpreter: Interpreter::new(program),
+            lines,
+            positions,
+            lengths: vec![],
+            output: vec![],
+            steps: 0,
+            speed: 8.0,
+            paused: false,
+            budget: 0.0,
+            input: None,
+            stopped: None,
+        }
+    }
+
+    /// Sets the length in bytes of each instruction in the source, as found by
+    /// [`Lexer::instruction_lengths`](crate::lexer::Lexer::instruction_lengths), to highlight
+    /// the whole instruction.
+    pub fn lengths(mut self, lengths: Vec<usize>) -> Self {
+        self.lengths = lengths;
+        self
+    }
+
+    /// Sets the speed in steps per second.
+    pub fn speed(mut self, speed: f64) -> Self {
+        self.speed = speed.clamp(SPEEDS.0, SPEEDS.1);
+        self
+    }
+
+    /// Starts paused, to go on with single steps.
+    pub fn paused(mut self, paused: bool) -> Self {
+        self.paused = paused;
+        self
+    }
+
+    /// Handles a key. Returns `false` if the view should be closed.
+    ///
+    /// `Space` pauses and resumes, `s` or `→` runs a single step and pauses, `+` and `-`
+    /// double and halve the speed, and `q` or `Esc` quit. While the program waits for input,
+    /// keys are typed into it and `Enter` gives the line to the program.
+    pub fn handle(&mut self, key: KeyEvent) -> bool {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return false;
+        }
+        if let Some(input) = &mut self.input {
+            match key.code {
+                KeyCode::Char(c) => input.push(c),
+                KeyCode::Backspace => {
+                    input.pop();
+                }
+                KeyCode::Enter => {
+                    let mut line = self.input.take().unwrap();
+                    line.push('\n');
+                    self.interpreter.push_input(line.as_bytes());
+                }
+                KeyCode::Esc => return false,
+                _ => {}
+            }
+            return true;
+        }
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Char(' ') => {
+                self.paused = !self.paused;
+                self.budget = 0.0;
+            }
+            KeyCode::Char('s') | KeyCode::Right => {
+                self.paused = true;
+                self.step();
+            }
+            KeyCode::Char('+' | '=') => self.speed = (self.speed * 2.0).min(SPEEDS.1),
+            KeyCode::Char('-') => self.speed = (self.speed / 2.0).max(SPEEDS.0),
+            _ => {}
+        }
+        true
+    }
+
+    /// Runs the steps due for `elapsed` at the current speed, unless the program is paused,
+    /// waits for input or stopped.
+    pub fn tick(&mut self, elapsed: Duration) {
+        if self.paused || self.input.is_some() || self.stopped.is_some() {
+            self.budget = 0.0;
+            return;
+        }
+        // Steps owed for more than a second are dropped, e.g. after the process was suspended.
+        self.budget = (self.budget + elapsed.as_secs_f64() * self.speed).min(self.speed);
+        while self.budget >= 1.0 && self.input.is_none() && self.stopped.is_none() {
+            self.budget -= 1.0;
+            self.step();
+        }
+    }
+
+    /// Renders the view on the whole frame.
+    pub fn draw(&self, frame: &mut Frame) {
+        let [main, output, help] = Layout::vertical([
+            Constraint::Min(5),
+            Constraint::Length(6),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [source, machine] =
+            Layout::horizontal([Constraint::Min(20), Constraint::Length(26)]).areas(main);
+        self.draw_source(frame, source);
+        self.draw_machine(frame, machine);
+        self.draw_output(frame, output);
+        frame.render_widget(Paragraph::new(self.help()), help);
+    }
+
+    /// Runs the view in `terminal` until it's closed, reading keys from the terminal.
+    pub fn run<B: Backend>(&mut self, terminal: &mut Terminal<B>) -> Result<()> {
+        let mut last = Instant::now();
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            if event::poll(FRAME)? {
+                if let TerminalEvent::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press && !self.handle(key) {
+                        return Ok(());
+                    }
+                }
+            }
+            self.tick(last.elapsed());
+            last = Instant::now();
+        }
+    }
+
+    fn step(&mut self) {
+        if self.stopped.is_some() {
+            return;
+        }
+        match self.interpreter.step() {
+            Event::Stepped | Event::Jumped { .. } => self.steps += 1,
+            Event::Output(output) => {
+                self.steps += 1;
+                self.output.extend(output);
+            }
+            Event::NeedInput => self.input = Some(String::new()),
+            Event::Halted => self.stopped = Some("The program ended".to_string()),
+            Event::Error(e) => self.stopped = Some(e.to_string()),
+        }
+    }
+
+    fn draw_source(&self, frame: &mut Frame, area: Rect) {
+        let program_counter = self.interpreter.program_counter();
+        let current = self.positions.get(program_counter);
+        let length = self.lengths.get(program_counter).copied().unwrap_or(3);
+        let lines = self
+            .lines
+            .iter()
+            .enumerate()
+            .map(|(index, line)| match current {
+                Some(&(number, column)) if number == index + 1 => {
+                    let start = (column - 1).min(line.len());
+                    let end = (start + length).min(line.len());
+                    Line::from(vec![
+                        Span::raw(text(&line[..start])),
+                        Span::styled(
+                            text(&line[start..end]),
+                            Style::new().add_modifier(Modifier::REVERSED | Modifier::BOLD),
+                        ),
+                        Span::raw(text(&line[end..])),
+                    ])
+                }
+                _ => Line::raw(text(line)),
+            });
+        // Keeps the current instruction in the middle.
+        let (height, width) = (area.height.saturating_sub(2), area.width.saturating_sub(2));
+        let (line, column) = current.copied().unwrap_or((1, 1));
+        let scroll = (
+            line.saturating_sub(1 + height as usize / 2) as u16,
+            (column + length).saturating_sub(width as usize) as u16,
+        );
+        let source = Paragraph::new(lines.collect::<Vec<_>>())
+            .block(Block::bordered().title(" Source "))
+            .scroll(scroll);
+        frame.render_widget(source, area);
+    }
+
+    fn draw_machine(&self, frame: &mut Frame, area: Rect) {
+        let interpreter = &self.interpreter;
+        let program_counter = interpreter.program_counter();
+        let instruction = interpreter
+            .program()
+            .get(program_counter)
+            .map_or("-", |instruction| instruction.as_str());
+        let register = interpreter
+            .register()
+            .map_or("-".to_string(), |value| value.to_string());
+        let speed = if self.paused {
+            "paused".to_string()
+        } else {
+            format!("{}/s", self.speed)
+        };
+        let mut lines = vec![
+            Line::raw(format!("step     {}", self.steps)),
+            Line::raw(format!("pc       {program_counter} ({instruction})")),
+            Line::raw(format!("register {register}")),
+            Line::raw(format!("speed    {speed}")),
+            Line::raw(""),
+        ];
+
+        // The tape scrolls so that the pointer stays in the middle.
+        let memory = interpreter.memory();
+        let pointer = interpreter.pointer();
+        let rows = (area.height as usize)
+            .saturating_sub(2 + lines.len())
+            .max(1);
+        let first = pointer
+            .saturating_sub(rows / 2)
+            .min(memory.len().saturating_sub(rows));
+        for (index, value) in memory.iter().enumerate().skip(first).take(rows) {
+            let row = format!("{index:>6} {value:>12}");
+            lines.push(if index == pointer {
+                Line::styled(
+                    format!("▶{row}"),
+                    Style::new().add_modifier(Modifier::REVERSED),
+                )
+            } else {
+                Line::raw(format!(" {row}"))
+            });
+        }
+        let machine = Paragraph::new(lines).block(Block::bordered().title(" Machine "));
+        frame.render_widget(machine, area);
+    }
+
+    fn draw_output(&self, frame: &mut Frame, area: Rect) {
+        let output = String::from_utf8_lossy(&self.output);
+        let lines: Vec<_> = output.split('\n').collect();
+        // Shows the end of the output.
+        let height = area.height.saturating_sub(2) as usize;
+        let lines: Vec<_> = lines[lines.len().saturating_sub(height)..]
+            .iter()
+            .map(|&line| Line::raw(line.replace('\t', "    ")))
+            .collect();
+        let output = Paragraph::new(lines).block(Block::bordered().title(" Output "));
+        frame.render_widget(output, area);
+    }
+
+    fn help(&self) -> Line<'_> {
+        if let Some(input) = &self.input {
+            Line::from(vec![
+                Span::styled("Input: ", Style::new().add_modifier(Modifier::BOLD)),
+                Span::raw(format!("{input}_  (Enter to give it, Esc to quit)")),
+            ])
+        } else if let Some(reason) = &self.stopped {
+            Line::raw(format!("{reason}. q: quit"))
+        } else {
+            Line::raw("space: pause/resume  s: step  +/-: speed  q: quit")
+        }
+    }
+}
+
+/// Renders bytes of the source, with tabs as spaces.
+fn text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).replace('\t', "    ")
+}
+
+#[cfg(test)]
+mod tests {
+    use ratatui::{backend::TestBackend, buffer::Buffer};
+
+    use super::*;
+    use crate::instruction::Instruction::*;
+
+    /// Renders `tui` on a headless terminal, returning the rows of the screen and the buffer.
+    fn render(tui: &Tui) -> (Vec<String>, Buffer) {
+        let mut terminal = Terminal::new(TestBackend::new(60, 16)).unwrap();
+        terminal.draw(|frame| tui.draw(frame)).unwrap();
+        let buffer = terminal.backend().buffer().clone();
+        let rows = (0..buffer.area.height)
+            .map(|y| {
+                (0..buffer.area.width)
+                    .map(|x| buffer[(x, y)].symbol())
+                    .collect()
+            })
+            .collect();
+        (rows, buffer)
+    }
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn tui_works() {
+        let source = b"MoO MoO\nOOM OOM";
+        // MoO MoO OOM OOM
+        let program = vec![IncrementByte, IncrementByte, WriteStdout, WriteStdout];
+        let positions = vec![(1, 1), (1, 5), (2, 1), (2, 5)];
+        let mut tui = Tui::new(program, source, Some(positions)).paused(true);
+
+        assert!(tui.handle(key(KeyCode::Char('s'))));
+        let (screen, buffer) = render(&tui);
+        assert!(screen[0].starts_with("┌ Source"));
+        assert!(screen[1].contains("│MoO MoO"));
+        assert!(screen[1].contains("step     1"));
+        assert!(screen[2].contains("pc       1 (MoO)"));
+        assert!(screen
+            .iter()
+            .any(|row| row.contains("▶     0            1")));
+        // The second `MoO` is highlighted.
+        assert!(buffer[(5, 1)].modifier.contains(Modifier::REVERSED));
+        assert!(!buffer[(1, 1)].modifier.contains(Modifier::REVERSED));
+
+        // Runs 4 steps per second.
+        tui = tui.speed(4.0);
+        tui.handle(key(KeyCode::Char(' ')));
+        tui.tick(Duration::from_millis(500));
+        assert_eq!(tui.steps, 3);
+        assert!(render(&tui).0[10].contains("│2 "));
+
+        tui.tick(Duration::from_secs(1));
+        let (screen, _) = render(&tui);
+        assert!(screen[10].contains("│22 "));
+        assert!(screen[15].starts_with("The program ended. q: quit"));
+        assert!(!tui.handle(key(KeyCode::Char('q'))));
+    }
+
+    #[test]
+    fn tui_highlights_whole_tokens() {
+        let source = b"MOOOO MOOOO";
+        let positions = vec![(1, 1), (1, 7)];
+        let tui = Tui::new(vec![WriteStdout, WriteStdout], source, Some(positions))
+            .lengths(vec![5, 5])
+            .paused(true);
+
+        let (_, buffer) = render(&tui);
+        assert!((1..6).all(|x| buffer[(x, 1)].modifier.contains(Modifier::REVERSED)));
+        assert!(!buffer[(6, 1)].modifier.contains(Modifier::REVERSED));
+    }
+
+    #[test]
+    fn tui_reads_input() {
+        // Moo Moo
+        let mut tui = Tui::new(vec![ReadOrWrite, ReadOrWrite], b"", None);
+        tui.tick(Duration::from_secs(1));
+        assert!(render(&tui).0[15].starts_with("Input: _"));
+
+        for c in "A".chars() {
+            tui.handle(key(KeyCode::Char(c)));
+        }
+        tui.handle(key(KeyCode::Enter));
+        tui.tick(Duration::from_secs(1));
+        let (screen, _) = render(&tui);
+        assert!(screen[1].contains("│Moo Moo"));
+        assert!(screen[10].contains("│A "));
+        assert_eq!(tui.interpreter.memory()[0], 65);
+    }
+}
@@ -0,0 +1,222 @@
+use std::{fmt::Write, ops::RangeInclusive};
+
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+
+use crate::interpreter::Interpreter;
+
+/// Number of memory blocks per line of [`table`].
+const BLOCKS_PER_LINE: usize = 10;
+/// Number of blocks of 0 in a row from which [`dump`] leaves them out.
+const ZEROS_LEFT_OUT: usize = 3;
+
+/// Renders the memory blocks within `radius` of the pointer on one line, along with the
+/// register, e.g. `0..=5: [3] 0 0 0 0 0 | pointer 0 | register -`.
+pub fn tape(interpreter: &Interpreter, radius: usize) -> String {
+    let memory = interpreter.memory();
+    let pointer = interpreter.pointer();
+    let start = pointer.saturating_sub(radius);
+    let end = (pointer + radius).min(memory.len() - 1);
+
+    let mut tape = format!("{start}..={end}:");
+    push_blocks(&mut tape, interpreter, start..=end);
+    write!(tape, " | pointer {pointer} | register ").unwrap();
+    match interpreter.register() {
+        Some(value) => write!(tape, "{value}").unwrap(),
+        None => tape.push('-'),
+    }
+    tape
+}
+
+/// Renders the memory blocks in `range`, several per line, each line starting with the index of
+/// its first block.
+pub fn table(interpreter: &Interpreter, range: RangeInclusive<usize>) -> String {
+    let width = range.end().to_string().len();
+    let mut table = String::new();
+    for first in range.clone().step_by(BLOCKS_PER_LINE) {
+        let last = (first + BLOCKS_PER_LINE - 1).min(*range.end());
+        write!(table, "{first:>width$}:").unwrap();
+        push_blocks(&mut table, interpreter, first..=last);
+        table.push('\n');
+    }
+    table
+}
+
+/// Renders the memory blocks from the first to the last one which isn't 0, or to the pointer if
+/// it's further, as a table of their hexadecimal, decimal and ASCII values. The current block is
+/// in brackets and the register is on the last line. Runs of blocks of 0 are left out, e.g.
+///
+/// ```text
+/// program counter 12 | pointer 5
+///    block       hex         dec  ascii
+///        0  00000048          72  'H'
+///        *  3 blocks of 0
+///        4  ffffffff          -1
+///      [5]  00000041          65  'A'
+/// register  00000041          65  'A'
+/// ```
+pub fn dump(interpreter: &Interpreter) -> String {
+    let range = used_range(interpreter);
+    let memory = interpreter.memory();
+    let width = (range.end().to_string().len() + 2).max("register".len());
+    let mut dump = format!(
+        "program counter {} | pointer {}\n",
+        interpreter.program_counter(),
+        interpreter.pointer()
+    );
+    writeln!(
+        dump,
+        "{:>width$}  {:>8}  {:>10}  ascii",
+        "block", "hex", "dec"
+    )
+    .unwrap();
+
+    let mut index = *range.start();
+    while index <= *range.end() {
+        let zeros = memory[index..=*range.end()]
+            .iter()
+            .enumerate()
+            .take_while(|&(offset, &value)| value == 0 && index + offset != interpreter.pointer())
+            .count();
+        if zeros >= ZEROS_LEFT_OUT {
+            writeln!(dump, "{:>width$}  {zeros} blocks of 0", "*").unwrap();
+            index += zeros;
+            continue;
+        }
+        let label = if index == interpreter.pointer() {
+            format!("[{index}]")
+        } else {
+            index.to_string()
+        };
+        push_row(&mut dump, &label, width, Some(memory[index]));
+        index += 1;
+    }
+    push_row(&mut dump, "register", width, interpreter.register());
+    dump
+}
+
+/// The state rendered by [`dump`] as JSON, with the blocks from `start` in `memory`.
+pub fn dump_json(interpreter: &Interpreter) -> Value {
+    let range = used_range(interpreter);
+    json!({
+        "program_counter": interpreter.program_counter(),
+        "pointer": interpreter.pointer(),
+        "register": interpreter.register(),
+        "start": range.start(),
+        "memory": &interpreter.memory()[range],
+    })
+}
+
+/// Parses the `[START] [END]` arguments of a command printing memory blocks. `END` defaults to
+/// the last block which isn't 0, or to the pointer if it's further.
+pub fn parse_range(words: &[&str], interpreter: &Interpreter) -> Result<RangeInclusive<usize>> {
+    let memory = interpreter.memory();
+    let last = memory
+        .iter()
+        .rposition(|&value| value != 0)
+        .unwrap_or_default()
+        .max(interpreter.pointer());
+    let bound = |index: usize, default: usize| -> Result<usize> {
+        match words.get(index) {
+            Some(word) => word
+                .parse()
+                .with_context(|| format!("`{word}` isn't a memory block")),
+            None => Ok(default),
+        }
+    };
+    if words.len() > 2 {
+        bail!("Expected at most START and END");
+    }
+    let start = bound(0, 0)?;
+    let end = bound(1, last)?.min(memory.len() - 1);
+    if start > end {
+        bail!("The range {start}..={end} is empty");
+    }
+    Ok(start..=end)
+}
+
+/// The blocks from the first to the last one which isn't 0, extended to the pointer.
+fn used_range(interpreter: &Interpreter) -> RangeInclusive<usize> {
+    let memory = interpreter.memory();
+    let pointer = interpreter.pointer();
+    let first = memory
+        .iter()
+        .position(|&value| value != 0)
+        .unwrap_or(pointer);
+    let last = memory
+        .iter()
+        .rposition(|&value| value != 0)
+        .unwrap_or(pointer);
+    first.min(pointer)..=last.max(pointer)
+}
+
+/// Pushes a line of [`dump`] for `value`, or dashes if there is none.
+fn push_row(output: &mut String, label: &str, width: usize, value: Option<i32>) {
+    match value {
+        Some(value) => {
+            write!(output, "{label:>width$}  {:08x}  {value:>10}", value as u32).unwrap();
+            if (0..128).contains(&value) {
+                write!(output, "  {:?}", value as u8 as char).unwrap();
+            }
+            output.push('\n');
+        }
+        None => writeln!(output, "{label:>width$}  {:>8}  {:>10}", "-", "-").unwrap(),
+    }
+}
+
+/// Pushes the values of the blocks in `range`, with the current one in brackets.
+fn push_blocks(output: &mut String, interpreter: &Interpreter, range: RangeInclusive<usize>) {
+    for index in range {
+        let value = interpreter.memory()[index];
+        if index == interpreter.pointer() {
+            write!(output, " [{value}]").unwrap();
+        } else {
+            write!(output, " {value}").unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruction::Instruction::*;
+
+    #[test]
+    fn dump_works() {
+        // MoO moO moO moO moO MOo moO MoO MMM
+        let program = vec![
+            IncrementByte,
+            IncrementPointer,
+            IncrementPointer,
+            IncrementPointer,
+            IncrementPointer,
+            DecrementByte,
+            IncrementPointer,
+            IncrementByte,
+            CopyOrPaste,
+        ];
+        let interpreter = Interpreter::new(program).run().unwrap();
+
+        assert_eq!(
+            dump(&interpreter),
+            "program counter 9 | pointer 5
+   block       hex         dec  ascii
+       0  00000001           1  '\\u{1}'
+       *  3 blocks of 0
+       4  ffffffff          -1
+     [5]  00000001           1  '\\u{1}'
+register  00000001           1  '\\u{1}'
+"
+        );
+        assert_eq!(
+            dump_json(&interpreter),
+            json!({
+                "program_counter": 9,
+                "pointer": 5,
+                "register": 1,
+                "start": 0,
+                "memory": [1, 0, 0, 0, -1, 1],
+            })
+        );
+    }
+}